@@ -10,27 +10,87 @@
 
 //! Basic data structures for representing a book.
 
+use std::fmt;
+use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::iter;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+
+use config::Config;
+use error::{CliResult, Error};
+use markdown::{self, ListMarker};
+use term::Term;
+
+/// The extensions of the Markdown files chapters are written in.
+pub const MARKDOWN_EXTENSIONS: &'static [&'static str] = &["md", "markdown"];
+
+/// Whether `path` has one of the `MARKDOWN_EXTENSIONS`.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).map_or(false, |e| MARKDOWN_EXTENSIONS.contains(&e))
+}
+
+/// What an entry of a book's table of contents stands for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ItemKind {
+    /// A chapter, with a page of its own.
+    Chapter,
+    /// A chapter without a section number, such as a foreword or a glossary.
+    Unnumbered,
+    /// The title of a part of the book, grouping the chapters after it. Has
+    /// no page and no section number.
+    PartTitle,
+    /// A rule separating parts of the book.
+    Separator,
+}
 
 pub struct BookItem {
     pub title: String,
     pub path: PathBuf,
     pub path_to_root: PathBuf,
     pub children: Vec<BookItem>,
+    pub kind: ItemKind,
+}
+
+impl BookItem {
+    /// Whether this is a chapter that has not been written yet, i.e. one
+    /// without a file.
+    pub fn is_draft(&self) -> bool {
+        match self.kind {
+            ItemKind::Chapter | ItemKind::Unnumbered => self.path.as_os_str().is_empty(),
+            ItemKind::PartTitle | ItemKind::Separator => false,
+        }
+    }
+
+    /// The path of the chapter's page in the built book.
+    pub fn page(&self) -> PathBuf {
+        self.path.with_extension("html")
+    }
+
+    fn heading(kind: ItemKind, title: String) -> BookItem {
+        BookItem {
+            title: title,
+            path: PathBuf::new(),
+            path_to_root: PathBuf::new(),
+            children: vec![],
+            kind: kind,
+        }
+    }
 }
 
 pub struct Book {
     pub chapters: Vec<BookItem>,
 }
 
-/// A depth-first iterator over a book.
+/// A depth-first iterator over the chapters of a book, along with their
+/// section numbers. Unnumbered chapters get an empty section number, and part
+/// titles and separators are skipped.
 pub struct BookItems<'a> {
     cur_items: &'a [BookItem],
     cur_idx: usize,
-    stack: Vec<(&'a [BookItem], usize)>,
+    /// Section number of the last chapter visited in `cur_items`.
+    cur_number: usize,
+    stack: Vec<(&'a [BookItem], usize, usize)>,
 }
 
 impl<'a> Iterator for BookItems<'a> {
@@ -41,25 +101,36 @@ impl<'a> Iterator for BookItems<'a> {
             if self.cur_idx >= self.cur_items.len() {
                 match self.stack.pop() {
                     None => return None,
-                    Some((parent_items, parent_idx)) => {
+                    Some((parent_items, parent_idx, parent_number)) => {
                         self.cur_items = parent_items;
                         self.cur_idx = parent_idx + 1;
+                        self.cur_number = parent_number;
                     }
                 }
             } else {
                 let cur = self.cur_items.get(self.cur_idx).unwrap();
-
                 let mut section = "".to_string();
-                for &(_, idx) in &self.stack {
-                    section.push_str(&(idx + 1).to_string()[..]);
-                    section.push('.');
+                match cur.kind {
+                    ItemKind::Chapter => {
+                        self.cur_number += 1;
+                        for &(_, _, number) in &self.stack {
+                            section.push_str(&number.to_string()[..]);
+                            section.push('.');
+                        }
+                        section.push_str(&self.cur_number.to_string()[..]);
+                        section.push('.');
+                    }
+                    ItemKind::Unnumbered => {}
+                    ItemKind::PartTitle | ItemKind::Separator => {
+                        self.cur_idx += 1;
+                        continue;
+                    }
                 }
-                section.push_str(&(self.cur_idx + 1).to_string()[..]);
-                section.push('.');
 
-                self.stack.push((self.cur_items, self.cur_idx));
+                self.stack.push((self.cur_items, self.cur_idx, self.cur_number));
                 self.cur_items = &cur.children[..];
                 self.cur_idx = 0;
+                self.cur_number = 0;
                 return Some((section, cur))
             }
         }
@@ -71,101 +142,720 @@ impl Book {
         BookItems {
             cur_items: &self.chapters[..],
             cur_idx: 0,
+            cur_number: 0,
             stack: Vec::new(),
         }
     }
 }
 
-/// Construct a book by parsing a summary (markdown table of contents).
-pub fn parse_summary(input: &mut Read, src: &Path) -> Result<Book, Vec<String>> {
-    fn collapse(stack: &mut Vec<BookItem>,
-                top_items: &mut Vec<BookItem>,
-                to_level: usize) {
-        loop {
-            if stack.len() < to_level { return }
-            if stack.len() == 1 {
-                top_items.push(stack.pop().unwrap());
-                return;
+/// A filter picking out chapters, or single code blocks of a chapter.
+enum Filter {
+    /// A chapter's path, such as `intro.md`, optionally followed by the line
+    /// a code block starts on, as in `intro.md:12`.
+    Path(PathBuf, Option<usize>),
+    /// A section number, such as `3.2`, which also matches its subsections.
+    Section(String),
+    /// Part of a chapter's title, in any case.
+    Title(String),
+}
+
+impl Filter {
+    fn parse(s: &str) -> Filter {
+        let s = s.trim_left_matches("./");
+        if !s.is_empty() && s.chars().all(|c| c.is_digit(10) || c == '.') {
+            return Filter::Section(format!("{}.", s.trim_right_matches('.')));
+        }
+        if let Some(colon) = s.rfind(':') {
+            if let Ok(line) = s[colon + 1..].parse() {
+                if s[..colon].ends_with(".md") {
+                    return Filter::Path(PathBuf::from(&s[..colon]), Some(line));
+                }
             }
+        }
+        if s.ends_with(".md") {
+            Filter::Path(PathBuf::from(s), None)
+        } else {
+            Filter::Title(s.to_lowercase())
+        }
+    }
+
+    /// Whether the filter matches the chapter numbered `section`, or some of
+    /// its code.
+    fn matches(&self, section: &str, item: &BookItem) -> bool {
+        match *self {
+            Filter::Path(ref path, _) => *path == item.path,
+            Filter::Section(ref number) => section.starts_with(&number[..]),
+            Filter::Title(ref title) => {
+                markdown::inline_to_text(&item.title).to_lowercase().contains(&title[..])
+            }
+        }
+    }
+
+    /// The line of the code block the filter picks out, if just one.
+    fn line(&self) -> Option<usize> {
+        match *self {
+            Filter::Path(_, line) => line,
+            Filter::Section(..) | Filter::Title(..) => None,
+        }
+    }
+}
+
+/// The chapters of a book to work on: those matching any of a list of
+/// filters, if there are any, except those matching one of a list of filters
+/// to skip.
+pub struct Selection {
+    only: Vec<Filter>,
+    skip: Vec<Filter>,
+}
+
+/// The code blocks of a chapter picked out by a `Selection`, by the line
+/// they start on.
+pub struct Lines {
+    /// Only these blocks, if not all of them.
+    pub only: Option<Vec<usize>>,
+    pub skip: Vec<usize>,
+}
+
+impl Lines {
+    pub fn is_everything(&self) -> bool {
+        self.only.is_none() && self.skip.is_empty()
+    }
+
+    pub fn includes(&self, line: usize) -> bool {
+        self.only.as_ref().map_or(true, |only| only.contains(&line)) &&
+            !self.skip.contains(&line)
+    }
+}
+
+impl Selection {
+    /// Select the chapters matching any of `only`, or all of them if it is
+    /// empty, then leave out the ones matching any of `skip`.
+    pub fn new(only: &[String], skip: &[String]) -> Selection {
+        Selection {
+            only: only.iter().map(|s| Filter::parse(s)).collect(),
+            skip: skip.iter().map(|s| Filter::parse(s)).collect(),
+        }
+    }
+
+    pub fn everything() -> Selection {
+        Selection { only: vec![], skip: vec![] }
+    }
 
-            let tip = stack.pop().unwrap();
-            let last = stack.len() - 1;
-            stack[last].children.push(tip);
+    pub fn is_everything(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    /// Whether the chapter numbered `section` is selected, at least in part.
+    pub fn includes(&self, section: &str, item: &BookItem) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|f| f.matches(section, item))) &&
+            !self.skip.iter().any(|f| f.matches(section, item) && f.line().is_none())
+    }
+
+    /// Which code blocks of the chapter numbered `section` are selected.
+    pub fn lines(&self, section: &str, item: &BookItem) -> Lines {
+        let only: Vec<&Filter> = self.only.iter().filter(|f| f.matches(section, item)).collect();
+        let only = if self.only.is_empty() || only.iter().any(|f| f.line().is_none()) {
+            None
+        } else {
+            Some(only.iter().filter_map(|f| f.line()).collect())
+        };
+        Lines {
+            only: only,
+            skip: self.skip.iter()
+                           .filter(|f| f.matches(section, item))
+                           .filter_map(|f| f.line())
+                           .collect(),
         }
     }
+}
 
+/// Resolve `.` and `..` in a relative path, without looking at the file
+/// system. `None` if the path leads out of the directory it is relative to,
+/// or to the directory itself.
+pub fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => if !normalized.pop() { return None },
+            Component::Normal(part) => normalized.push(part),
+            Component::RootDir | Component::Prefix(..) => return None,
+        }
+    }
+    if normalized.as_os_str().is_empty() { None } else { Some(normalized) }
+}
+
+/// A problem found in a summary, along with where it was found and, when
+/// there is an obvious one, how to fix it.
+#[derive(Debug)]
+pub struct SummaryError {
+    pub file: PathBuf,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    /// The offending line.
+    pub snippet: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl SummaryError {
+    fn new(file: &Path, line: usize, snippet: &str, offset: usize, message: String,
+           suggestion: Option<String>) -> SummaryError {
+        SummaryError {
+            file: file.to_path_buf(),
+            line: line,
+            column: snippet[..offset].chars().count() + 1,
+            snippet: snippet.to_string(),
+            message: message,
+            suggestion: suggestion,
+        }
+    }
+}
+
+impl fmt::Display for SummaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = iter::repeat(" ").take(self.line.to_string().len()).collect::<String>();
+        write!(f, "{}\n", self.message)?;
+        write!(f, "{}--> {}:{}:{}\n", gutter, self.file.display(), self.line, self.column)?;
+        write!(f, "{} |\n", gutter)?;
+        write!(f, "{} | {}\n", self.line, self.snippet)?;
+        write!(f, "{} | {}^", gutter,
+               iter::repeat(" ").take(self.column - 1).collect::<String>())?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, "\n{} = help: {}", gutter, suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// A summary entry that has been split into its parts, but not yet checked
+/// against the rest of the book.
+struct Entry<'a> {
+    /// The list item marker; unnumbered chapters have none.
+    marker: Option<ListMarker>,
+    /// The title, as Markdown.
+    title: &'a str,
+    path: String,
+    path_offset: usize,
+}
+
+/// A line of the summary that means something to us.
+enum Line<'a> {
+    Entry(Entry<'a>),
+    /// A heading, with its text as Markdown.
+    Heading(&'a str),
+    /// A thematic break, e.g. `---`.
+    Rule,
+}
+
+/// A problem with a single line of the summary: the byte offset it was found
+/// at, the message and an optional suggestion.
+type LineError = (usize, String, Option<String>);
+
+/// Split a line of the summary into an entry, or recognize it as a heading
+/// or a rule. Other lines are skipped.
+fn parse_line(line: &str) -> Result<Option<Line>, LineError> {
+    let (_, offset) = markdown::indentation(line);
+    if let Some(c) = line[offset..].chars().next() {
+        if c.is_whitespace() && markdown::list_marker(line.trim_left()).is_some() {
+            return Err((offset, format!("unexpected whitespace character {:?} in indentation", c),
+                        Some("indent list items with spaces or tabs".to_string())));
+        }
+    }
+
+    if markdown::is_thematic_break(line) {
+        return Ok(Some(Line::Rule));
+    }
+    if let Some(heading) = markdown::atx_heading(line) {
+        return Ok(Some(Line::Heading(heading)));
+    }
+
+    let (marker, link_offset) = match markdown::list_marker(line) {
+        Some(marker) => {
+            let link_offset = marker.content_offset;
+            (Some(marker), link_offset)
+        }
+        None => {
+            let (indent, offset) = markdown::indentation(line);
+            let rest = &line[offset..];
+            for bullet in &["*[", "-[", "+["] {
+                if rest.starts_with(bullet) {
+                    return Err((offset + 1, format!("missing space after `{}`", &bullet[..1]),
+                                Some(format!("write this as `{}{} {}`",
+                                             &line[..offset], &bullet[..1], &rest[1..]))));
+                }
+            }
+            // a link on a line of its own is an unnumbered chapter
+            if indent > 3 || !rest.starts_with('[') {
+                return Ok(None);
+            }
+            (None, offset)
+        }
+    };
+
+    // what comes before the link, for suggestions
+    let prefix = match marker {
+        Some(ref marker) => {
+            format!("{} ", line[..marker.content_offset].trim_right())
+        }
+        None => line[..link_offset].to_string(),
+    };
+    let content = &line[link_offset..];
+    if !content.starts_with('[') {
+        let title = if content.is_empty() { "Title" } else { content.trim() };
+        return Err((link_offset,
+                    format!("expected a link of the form `[title](path)` after `{}`",
+                            prefix.trim()),
+                    Some(format!("write this as `{}[{}](chapter.md)`", prefix, title))));
+    }
+
+    let end_bracket = match markdown::link_text_end(line, link_offset) {
+        Some(i) => i,
+        None => {
+            return Err((link_offset, "unclosed `[` in the title of this section".to_string(),
+                        Some("add a `]` after the title".to_string())));
+        }
+    };
+    let title = &line[link_offset + 1..end_bracket];
+    let start_paren = end_bracket + 1;
+    if !line[start_paren..].starts_with('(') {
+        let suggestion = if line[start_paren..].trim_left().starts_with('(') {
+            "remove the space between `]` and `(`".to_string()
+        } else {
+            format!("write this as `{}[{}](chapter.md)`", prefix, title)
+        };
+        return Err((start_paren,
+                    format!("expected `(` after the title of section '{}'", title),
+                    Some(suggestion)));
+    }
+    let destination = match markdown::link_destination(line, start_paren) {
+        Some(destination) => destination,
+        None => {
+            return Err((start_paren,
+                        format!("unclosed `(` in the path of section '{}'", title),
+                        Some("add a `)` after the path, and wrap paths containing \
+                              spaces in `<...>`".to_string())));
+        }
+    };
+
+    let trailing = &line[destination.end..];
+    if !trailing.trim().is_empty() {
+        let offset = line.len() - trailing.trim_left().len();
+        return Err((offset, format!("unexpected text after the link to section '{}'", title),
+                    Some("remove it, or move it into the title".to_string())));
+    }
+
+    Ok(Some(Line::Entry(Entry {
+        marker: marker,
+        title: title,
+        path: destination.url,
+        path_offset: destination.offset,
+    })))
+}
+
+/// Read and parse the summary of the book described by `config`, reporting
+/// every error in it.
+pub fn load(config: &Config, term: &mut Term) -> CliResult<Book> {
+    let path = config.summary_path();
+    let mut summary = File::open(&path).map_err(|e| Error::io(&path, e))?;
+    parse_summary(&mut summary, config).map_err(|errors| {
+        for error in &errors {
+            term.error(&error.to_string());
+        }
+        Error::Summary(errors.len())
+    })
+}
+
+/// Construct a book by parsing a summary (markdown table of contents).
+///
+/// Parsing carries on past errors, so that every problem in the summary is
+/// reported at once.
+pub fn parse_summary(input: &mut Read, config: &Config) -> Result<Book, Vec<SummaryError>> {
+    /// Close the innermost open section, adding it to its parent.
+    fn close(stack: &mut Vec<(BookItem, usize)>, top_items: &mut Vec<BookItem>) {
+        let (item, _) = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some(&mut (ref mut parent, _)) => parent.children.push(item),
+            None => top_items.push(item),
+        }
+    }
+
+    let src = &config.src;
+    let file = config.summary_path();
     let mut top_items = vec![];
-    let mut stack = vec![];
+    // the open sections, along with the column their text starts at
+    let mut stack: Vec<(BookItem, usize)> = vec![];
     let mut errors = vec![];
+    let mut seen_heading = false;
+    let mut seen_numbered = false;
+    let mut seen_suffix = false;
+    let mut has_introduction = false;
+    // the pages of the chapters so far, with the line and path of each
+    let mut pages: Vec<(PathBuf, usize, PathBuf)> = vec![];
 
-    // always include the introduction
-    top_items.push(BookItem {
-        title: "Introduction".to_string(),
-        path: PathBuf::from("README.md"),
-        path_to_root: PathBuf::from(""),
-        children: vec![],
-    });
-
-    for line_result in BufReader::new(input).lines() {
+    for (line_idx, line_result) in BufReader::new(input).lines().enumerate() {
+        let line_no = line_idx + 1;
         let line = match line_result {
             Ok(line) => line,
             Err(err) => {
-                errors.push(err.to_string());
+                errors.push(SummaryError::new(&file, line_no, "", 0, err.to_string(), None));
                 return Err(errors);
             }
         };
 
-        let star_idx = match line.find("*") { Some(i) => i, None => continue };
-
-        let start_bracket = star_idx + line[star_idx..].find("[").unwrap();
-        let end_bracket = start_bracket + line[start_bracket..].find("](").unwrap();
-        let start_paren = end_bracket + 1;
-        let end_paren = start_paren + line[start_paren..].find(")").unwrap();
-
-        let given_path = &line[start_paren + 1 .. end_paren];
-        let title = line[start_bracket + 1..end_bracket].to_string();
-        let indent = &line[..star_idx];
+        let entry = match parse_line(&line) {
+            Ok(Some(Line::Entry(entry))) => entry,
+            Ok(Some(Line::Heading(title))) => {
+                // the first heading is the summary's own title, unless some
+                // chapters came before it
+                if seen_heading || !stack.is_empty() || !top_items.is_empty() {
+                    if seen_suffix {
+                        errors.push(SummaryError::new(
+                            &file, line_no, &line, 0,
+                            format!("part '{}' follows the unnumbered chapters at the \
+                                     end of the book", title),
+                            Some("move those chapters after the last part".to_string())));
+                        continue;
+                    }
+                    while !stack.is_empty() {
+                        close(&mut stack, &mut top_items);
+                    }
+                    top_items.push(BookItem::heading(ItemKind::PartTitle, title.to_string()));
+                }
+                seen_heading = true;
+                continue;
+            }
+            Ok(Some(Line::Rule)) => {
+                while !stack.is_empty() {
+                    close(&mut stack, &mut top_items);
+                }
+                top_items.push(BookItem::heading(ItemKind::Separator, String::new()));
+                continue;
+            }
+            Ok(None) => continue,
+            Err((offset, message, suggestion)) => {
+                errors.push(SummaryError::new(&file, line_no, &line, offset, message, suggestion));
+                continue;
+            }
+        };
 
-        let path_from_root = match src.join(given_path).strip_prefix(src) {
-            Ok(p) => p.to_path_buf(),
+        let path_from_root = match src.join(&entry.path).strip_prefix(src) {
+            // an empty path marks a draft
+            _ if entry.path.is_empty() => PathBuf::new(),
+            Ok(p) => match normalize(p) {
+                Some(p) => p,
+                None => {
+                    errors.push(SummaryError::new(
+                        &file, line_no, &line, entry.path_offset,
+                        format!("path '{}' for section '{}' leads out of the directory of {}",
+                                entry.path, entry.title, config.summary.display()),
+                        Some(format!("move the chapter into the directory of {}",
+                                     config.summary.display()))));
+                    continue;
+                }
+            },
             Err(..) => {
-                errors.push(format!("paths in SUMMARY.md must be relative, \
-                                     but path '{}' for section '{}' is not.",
-                                     given_path, title));
-                PathBuf::new()
+                errors.push(SummaryError::new(
+                    &file, line_no, &line, entry.path_offset,
+                    format!("paths in {} must be relative, \
+                             but path '{}' for section '{}' is not.",
+                            config.summary.display(), entry.path, entry.title),
+                    Some(format!("make the path relative to the directory of {}",
+                                 config.summary.display()))));
+                continue;
             }
         };
-        let path_to_root = PathBuf::from(&iter::repeat("../")
-                                         .take(path_from_root.components().count() - 1)
-                                         .collect::<String>());
-        let item = BookItem {
-            title: title,
+        let depth = path_from_root.components().count().saturating_sub(1);
+        let path_to_root = PathBuf::from(&iter::repeat("../").take(depth).collect::<String>());
+        let mut item = BookItem {
+            title: entry.title.to_string(),
             path: path_from_root,
             path_to_root: path_to_root,
             children: vec![],
+            kind: ItemKind::Chapter,
         };
-        let level = indent.chars().map(|c| -> usize {
-            match c {
-                ' ' => 1,
-                '\t' => 4,
-                _ => unreachable!()
+
+        // every chapter needs a page of its own
+        if !item.is_draft() {
+            let page = item.page();
+            let clash = match pages.iter().find(|&&(ref other, _, _)| *other == page) {
+                Some(&(_, other_line, ref other_path)) if *other_path == item.path => {
+                    Some((format!("'{}' is already listed on line {}",
+                                  entry.path, other_line),
+                          "list every chapter only once".to_string()))
+                }
+                Some(&(_, other_line, ref other_path)) => {
+                    Some((format!("section '{}' would be rendered to '{}', as is '{}' \
+                                   on line {}",
+                                  entry.title, page.display(), other_path.display(),
+                                  other_line),
+                          "rename one of the files".to_string()))
+                }
+                None if page == Path::new("index.html") => {
+                    Some((format!("section '{}' would be rendered to 'index.html', which \
+                                   is taken by the book's introduction",
+                                  entry.title),
+                          "rename the file".to_string()))
+                }
+                None => None,
+            };
+            if let Some((message, suggestion)) = clash {
+                errors.push(SummaryError::new(&file, line_no, &line, entry.path_offset,
+                                              message, Some(suggestion)));
+                continue;
             }
-        }).sum::<usize>() / 4 + 1;
+            pages.push((page, line_no, item.path.clone()));
+        }
 
-        if level > stack.len() + 1 {
-            errors.push(format!("section '{}' is indented too deeply; \
-                                 found {}, expected {} or less",
-                                item.title, level, stack.len() + 1));
-        } else if level <= stack.len() {
-            collapse(&mut stack, &mut top_items, level);
+        let marker = match entry.marker {
+            Some(marker) => marker,
+            None => {
+                // a chapter outside of any list is unnumbered; after the
+                // numbered chapters, it starts the back matter
+                while !stack.is_empty() {
+                    close(&mut stack, &mut top_items);
+                }
+                seen_suffix = seen_numbered;
+                has_introduction |= item.path == Path::new("README.md");
+                item.kind = ItemKind::Unnumbered;
+                top_items.push(item);
+                continue;
+            }
+        };
+        if seen_suffix {
+            errors.push(SummaryError::new(
+                &file, line_no, &line, marker.offset,
+                format!("section '{}' follows the unnumbered chapters at the end of the book",
+                        item.title),
+                Some("move those chapters after the last list".to_string())));
+            continue;
+        }
+        if item.path == Path::new("README.md") {
+            errors.push(SummaryError::new(
+                &file, line_no, &line, entry.path_offset,
+                "README.md is the book's introduction, and cannot be a numbered chapter"
+                    .to_string(),
+                Some("list it as `[Title](README.md)` before the first list to give the \
+                      introduction a title".to_string())));
+            continue;
         }
-        stack.push(item)
+        seen_numbered = true;
+
+        // as in CommonMark, an item is nested inside the previous one if it
+        // is indented at least as far as that item's text
+        while stack.last().map_or(false, |&(_, column)| marker.indent < column) {
+            close(&mut stack, &mut top_items);
+        }
+        let parent_column = stack.last().map_or(0, |&(_, column)| column);
+        if marker.indent >= parent_column + 4 {
+            let suggestion = match stack.last() {
+                Some(&(ref parent, column)) => {
+                    format!("indent it by {} spaces to nest it inside section '{}'",
+                            column, parent.title)
+                }
+                None => "remove the indentation".to_string(),
+            };
+            errors.push(SummaryError::new(
+                &file, line_no, &line, marker.offset,
+                format!("section '{}' is indented too deeply; \
+                         found {} columns, expected fewer than {}",
+                        item.title, marker.indent, parent_column + 4),
+                Some(suggestion)));
+        }
+        stack.push((item, marker.content_column));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut top_items);
+    }
+
+    // always include the introduction, unless the summary gives it a title
+    // of its own
+    if !has_introduction {
+        top_items.insert(0, BookItem {
+            title: config.introduction.clone(),
+            path: PathBuf::from("README.md"),
+            path_to_root: PathBuf::from(""),
+            children: vec![],
+            kind: ItemKind::Unnumbered,
+        });
+    }
+    Ok(Book { chapters: top_items })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use config::Config;
+    use super::{parse_summary, Book, BookItem, ItemKind, SummaryError};
+
+    fn parse(summary: &str) -> Result<Book, Vec<SummaryError>> {
+        parse_summary(&mut summary.as_bytes(), &Config::default(Path::new("/book")))
+    }
+
+    /// The titles of the chapters, with those of their children in
+    /// parentheses.
+    fn outline(summary: &str) -> String {
+        fn titles(items: &[BookItem]) -> String {
+            items.iter().map(|item| {
+                if item.children.is_empty() {
+                    item.title.clone()
+                } else {
+                    format!("{} ({})", item.title, titles(&item.children))
+                }
+            }).collect::<Vec<_>>().join(", ")
+        }
+        match parse(summary) {
+            Ok(book) => titles(&book.chapters[1..]),
+            Err(errors) => panic!("{}", errors[0]),
+        }
+    }
+
+    /// The line, column and message of the only error in `summary`.
+    fn error(summary: &str) -> (usize, usize, String) {
+        match parse(summary) {
+            Ok(..) => panic!("no error in {:?}", summary),
+            Err(errors) => {
+                assert_eq!(errors.len(), 1, "{:?}", errors);
+                (errors[0].line, errors[0].column, errors[0].message.clone())
+            }
+        }
+    }
+
+    #[test]
+    fn nests_by_content_column() {
+        assert_eq!(outline("* [A](a.md)\n  * [B](b.md)\n* [C](c.md)"), "A (B), C");
+        assert_eq!(outline("* [A](a.md)\n * [B](b.md)"), "A, B");
+        assert_eq!(outline("*   [A](a.md)\n    * [B](b.md)\n  * [C](c.md)"), "A (B), C");
+        assert_eq!(outline("1. [A](a.md)\n   1. [B](b.md)\n      - [C](c.md)"), "A (B (C))");
+        assert_eq!(outline("10. [A](a.md)\n   * [B](b.md)"), "A, B");
+        assert_eq!(outline("* [A](a.md)\n\t* [B](b.md)"), "A (B)");
+    }
+
+    #[test]
+    fn closes_nested_sections() {
+        let summary = "* [A](a.md)\n  * [B](b.md)\n    * [C](c.md)\n* [D](d.md)";
+        assert_eq!(outline(summary), "A (B (C)), D");
+    }
+
+    #[test]
+    fn numbers_sections() {
+        let book = parse("* [A](a.md)\n  * [B](b.md)\n* [C](c.md)").ok().unwrap();
+        let sections: Vec<_> = book.iter().map(|(section, _)| section).collect();
+        assert_eq!(sections, ["", "1.", "1.1.", "2."]);
+    }
+
+    #[test]
+    fn titles_with_markdown() {
+        assert_eq!(outline("* [*Emphasis* and **strong**](a.md)"), "*Emphasis* and **strong**");
+        assert_eq!(outline("* [Brackets [nest]](a.md)"), "Brackets [nest]");
+        assert_eq!(outline("* [Escaped \\[ and \\]](a.md)"), "Escaped \\[ and \\]");
+        assert_eq!(outline("* [Parens) too](a.md)"), "Parens) too");
+        assert_eq!(outline("* [Code `]` and `)`](a.md)"), "Code `]` and `)`");
+        assert_eq!(outline("* [``a ` b``](a.md)"), "``a ` b``");
+    }
+
+    #[test]
+    fn paths() {
+        let book = parse("* [A](<a b.md>)\n* [B](dir/./c\\(1\\).md)\n* [C](d.md \"title\")")
+            .ok().unwrap();
+        let paths: Vec<_> = book.iter().map(|(_, item)| item.path.clone()).collect();
+        assert_eq!(paths, [Path::new("README.md"), Path::new("a b.md"),
+                           Path::new("dir/c(1).md"), Path::new("d.md")]);
+        let item = book.iter().nth(2).unwrap().1;
+        assert_eq!(item.path_to_root, Path::new("../"));
+    }
+
+    #[test]
+    fn parts_and_separators() {
+        let book = parse("# Summary\n\n* [A](a.md)\n\n# Part I\n\n* [B](b.md)\n\n---\n\n\
+                          * [C](c.md)").ok().unwrap();
+        let kinds: Vec<_> = book.chapters.iter().map(|item| item.kind).collect();
+        assert_eq!(kinds, [ItemKind::Unnumbered, ItemKind::Chapter, ItemKind::PartTitle,
+                           ItemKind::Chapter, ItemKind::Separator, ItemKind::Chapter]);
+        assert_eq!(book.chapters[2].title, "Part I");
+        let sections: Vec<_> = book.iter().map(|(section, _)| section).collect();
+        assert_eq!(sections, ["", "1.", "2.", "3."]);
+    }
+
+    #[test]
+    fn unnumbered_chapters() {
+        let book = parse("[Preface](preface.md)\n\n* [A](a.md)\n\n[Glossary](glossary.md)")
+            .ok().unwrap();
+        let kinds: Vec<_> = book.chapters.iter().map(|item| item.kind).collect();
+        assert_eq!(kinds, [ItemKind::Unnumbered, ItemKind::Unnumbered, ItemKind::Chapter,
+                           ItemKind::Unnumbered]);
+        let sections: Vec<_> = book.iter().map(|(section, _)| section).collect();
+        assert_eq!(sections, ["", "", "1.", ""]);
+    }
+
+    #[test]
+    fn draft_chapters() {
+        let book = parse("* [A](a.md)\n  * [B]()\n\n[Draft]()").ok().unwrap();
+        let drafts: Vec<_> = book.iter().map(|(_, item)| item.is_draft()).collect();
+        assert_eq!(drafts, [false, false, true, true]);
+    }
+
+    #[test]
+    fn skips_other_lines() {
+        assert_eq!(outline("Some text.\n\n* [A](a.md)\n\n    [Code](code.md)"), "A");
+    }
+
+    #[test]
+    fn reports_lines_and_columns() {
+        assert_eq!(error("# Summary\n\n* [A](a.md)\n  *[B](b.md)"),
+                   (4, 4, "missing space after `*`".to_string()));
+        assert_eq!(error("* [A(a.md)"),
+                   (1, 3, "unclosed `[` in the title of this section".to_string()));
+        assert_eq!(error("* [A] (a.md)"),
+                   (1, 6, "expected `(` after the title of section 'A'".to_string()));
+        assert_eq!(error("* [A](a.md"),
+                   (1, 6, "unclosed `(` in the path of section 'A'".to_string()));
+        assert_eq!(error("* [Ünïcödé](a.md) and more"),
+                   (1, 19, "unexpected text after the link to section 'Ünïcödé'".to_string()));
+        assert_eq!(error("* A"),
+                   (1, 3, "expected a link of the form `[title](path)` after `*`".to_string()));
+    }
+
+    #[test]
+    fn reports_bad_nesting() {
+        let (line, column, message) = error("* [A](a.md)\n      * [B](b.md)");
+        assert_eq!((line, column), (2, 7));
+        assert!(message.contains("indented too deeply"), "{}", message);
+    }
+
+    #[test]
+    fn reports_bad_paths() {
+        let (line, column, message) = error("* [A](../a.md)");
+        assert_eq!((line, column), (1, 7));
+        assert!(message.contains("leads out of"), "{}", message);
+        let (_, _, message) = error("* [A](a.md)\n* [B](./a.md)");
+        assert!(message.contains("already listed on line 1"), "{}", message);
+        let (_, _, message) = error("* [A](a.md)\n* [B](a.markdown)");
+        assert!(message.contains("would be rendered to 'a.html'"), "{}", message);
+        let (_, _, message) = error("* [Intro](README.md)");
+        assert!(message.contains("cannot be a numbered chapter"), "{}", message);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let errors = parse("* [A](a.md\n* [B](b.md)\n*[C](c.md)").err().unwrap();
+        let lines: Vec<_> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 3]);
     }
 
-    if errors.is_empty() {
-        collapse(&mut stack, &mut top_items, 1);
-        Ok(Book { chapters: top_items })
-    } else {
-        Err(errors)
+    #[test]
+    fn formats_errors() {
+        let errors = parse("\n\n\n\n\n\n\n\n\n* A").err().unwrap();
+        assert_eq!(errors[0].to_string(),
+                   "expected a link of the form `[title](path)` after `*`\n  \
+                    --> /book/SUMMARY.md:10:3\n   |\n10 | * A\n   |   ^\n   \
+                    = help: write this as `* [A](chapter.md)`");
     }
 }
@@ -13,23 +13,85 @@
 use std::env;
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::{self, BufWriter};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use rustc_back::tempdir::TempDir;
 
-use subcommand::Subcommand;
+use subcommand::{parse_jobs, parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
 use term::Term;
-use error::{err, CliResult, CommandResult};
+use error::{err, ChapterError, CliResult, CommandResult, Error};
 use book;
-use book::{Book, BookItem};
+use book::{Book, BookItem, ItemKind, Selection};
+use config::Config;
+use epub;
+use print;
+use manifest::{self, Manifest, MANIFEST_FILE};
+use markdown;
+use pool;
+use search;
+use template::Value;
+use theme::Theme;
 
 use rustdoc;
 
-struct Build;
+/// Where the chapter goes in the layout of a page, until it is split there.
+const CONTENT_MARKER: &'static str = "<!-- rustbook: content -->";
+
+/// Put right before and after the chapter in every page, so that other
+/// renderers can pick it out with `page_content`.
+const CONTENT_START: &'static str = "<!-- rustbook: content start -->";
+const CONTENT_END: &'static str = "<!-- rustbook: content end -->";
+
+struct Build {
+    book: BookArgs,
+    dest_dir: Option<PathBuf>,
+    strict: bool,
+    open: bool,
+    only: Vec<String>,
+    force: bool,
+    jobs: usize,
+    format: Format,
+}
+
+/// What to build a book as.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    /// Pages for the web, the default.
+    Html,
+    /// A single EPUB file, for e-readers.
+    Epub,
+    /// A single page with every chapter, for printing.
+    Print,
+}
+
+const BUILD_OPTS: &'static [Opt] = &[
+    Opt { short: "d", long: "dest-dir", value: "DIR",
+          help: "Build into DIR (default: the build-dir of book.toml, or _book)" },
+    Opt { short: "o", long: "open", value: "", help: "Open the book in a browser once built" },
+    Opt { short: "", long: "strict", value: "", help: "Fail if any chapter is still a draft" },
+    Opt { short: "", long: "only", value: "FILTER",
+          help: "Only render the chapters matching FILTER, as for `rustbook test`" },
+    Opt { short: "f", long: "force", value: "",
+          help: "Render every page, even those unchanged since the last build" },
+    Opt { short: "j", long: "jobs", value: "N", help: "Render N chapters at a time (default: 1)" },
+    Opt { short: "", long: "format", value: "FORMAT",
+          help: "Build the book as `html` pages, an `epub` file or a single page to \
+                 `print` (default: html)" },
+];
 
 pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
     if name == "build" {
-        Some(Box::new(Build))
+        Some(Box::new(Build {
+            book: BookArgs::new(),
+            dest_dir: None,
+            strict: false,
+            open: false,
+            only: vec![],
+            force: false,
+            jobs: 1,
+            format: Format::Html,
+        }))
     } else {
         None
     }
@@ -40,8 +102,24 @@ fn write_toc(book: &Book, current_page: &BookItem, out: &mut Write) -> io::Resul
                   section: &str,
                   current_page: &BookItem,
                   out: &mut Write) -> io::Result<()> {
-        for (i, item) in items.iter().enumerate() {
-            walk_item(item, &format!("{}{}.", section, i + 1)[..], current_page, out)?;
+        let mut number = 0;
+        for item in items {
+            match item.kind {
+                ItemKind::Chapter => {
+                    number += 1;
+                    walk_item(item, &format!("{}{}.", section, number)[..], current_page, out)?;
+                }
+                ItemKind::Unnumbered => {
+                    walk_item(item, "", current_page, out)?;
+                }
+                ItemKind::PartTitle => {
+                    writeln!(out, "<li class='part-title'>{}</li>",
+                             markdown::inline_to_html(&item.title))?;
+                }
+                ItemKind::Separator => {
+                    writeln!(out, "<li class='spacer'></li>")?;
+                }
+            }
         }
         Ok(())
     }
@@ -55,11 +133,22 @@ fn write_toc(book: &Book, current_page: &BookItem, out: &mut Write) -> io::Resul
             ""
         };
 
-        writeln!(out, "<li><a {} href='{}'><b>{}</b> {}</a>",
-                 class_string,
-                 current_page.path_to_root.join(&item.path).with_extension("html").display(),
-                 section,
-                 item.title)?;
+        let number = if section.is_empty() {
+            "".to_string()
+        } else {
+            format!("<b>{}</b> ", section)
+        };
+        if item.is_draft() {
+            writeln!(out, "<li><span class='draft'>{}{}</span>",
+                     number,
+                     markdown::inline_to_html(&item.title))?;
+        } else {
+            writeln!(out, "<li><a {} href='{}'>{}{}</a>",
+                     class_string,
+                     current_page.path_to_root.join(&item.path).with_extension("html").display(),
+                     number,
+                     markdown::inline_to_html(&item.title))?;
+        }
         if !item.children.is_empty() {
             writeln!(out, "<ol class='section'>")?;
             let _ = walk_items(&item.children[..], section, current_page, out);
@@ -70,151 +159,639 @@ fn write_toc(book: &Book, current_page: &BookItem, out: &mut Write) -> io::Resul
         Ok(())
     }
 
-    writeln!(out, "<div id='toc' class='mobile-hidden'>")?;
-    writeln!(out, "<ol class='chapter'>")?;
-    walk_items(&book.chapters[..], "", &current_page, out)?;
-    writeln!(out, "</ol>")?;
-    writeln!(out, "</div>")?;
+    walk_items(&book.chapters[..], "", &current_page, out)
+}
 
-    Ok(())
+/// Write the items of a list of the chapters in `items` and below them,
+/// numbered as in the table of contents of every page and linking to
+/// `href(item)`, for renderers with a table of contents of their own. Part
+/// titles and separators are left out, and so are drafts without chapters
+/// below them.
+pub fn toc_items<F>(items: &[BookItem], section: &str, href: &F, out: &mut String)
+    where F: Fn(&BookItem) -> String
+{
+    let mut number = 0;
+    for item in items {
+        let section = match item.kind {
+            ItemKind::Chapter => {
+                number += 1;
+                format!("{}{}.", section, number)
+            }
+            ItemKind::Unnumbered => String::new(),
+            ItemKind::PartTitle | ItemKind::Separator => continue,
+        };
+        let mut children = String::new();
+        toc_items(&item.children, &section, href, &mut children);
+
+        let title = markdown::inline_to_html(&item.title);
+        let label = if section.is_empty() { title } else { format!("{} {}", section, title) };
+        if !item.is_draft() {
+            out.push_str(&format!("<li><a href=\"{}\">{}</a>",
+                                  markdown::escape_html(&href(item)), label));
+        } else if !children.is_empty() {
+            out.push_str(&format!("<li><span>{}</span>", label));
+        } else {
+            continue;
+        }
+        if !children.is_empty() {
+            out.push_str(&format!("\n<ol>\n{}</ol>\n", children));
+        }
+        out.push_str("</li>\n");
+    }
+}
+
+/// The previous or next chapter of `from`, as the templates of a page see
+/// it, or `Value::Nothing` if there is none.
+fn neighbour(chapter: Option<&(String, &BookItem)>, from: &BookItem) -> Value {
+    match chapter {
+        Some(&(ref section, item)) => Value::Map(vec![
+            ("title", Value::Html(markdown::inline_to_html(&item.title))),
+            ("section", Value::Text(section.clone())),
+            ("link", Value::Text(page_link(from, item))),
+        ]),
+        None => Value::Nothing,
+    }
+}
+
+/// The relative URL of the page of `to` on the page of `from`.
+fn page_link(from: &BookItem, to: &BookItem) -> String {
+    from.path_to_root.join(to.page()).display().to_string()
+}
+
+/// Map a link to a chapter's Markdown source onto the chapter's page, e.g.
+/// `intro.md#usage` onto `intro.html#usage`. Links with a scheme, absolute
+/// links and links to anything but Markdown files are left alone.
+fn chapter_link(url: &str) -> Option<String> {
+    let end = url.find(|c| c == '#' || c == '?').unwrap_or(url.len());
+    let (path, rest) = url.split_at(end);
+    if markdown::has_scheme(path) || path.starts_with('/') {
+        return None;
+    }
+    match path.rfind('.').map(|i| (&path[..i], &path[i + 1..])) {
+        Some((stem, extension)) if !stem.is_empty() && !stem.ends_with('/') &&
+                                   book::MARKDOWN_EXTENSIONS.contains(&extension) => {
+            Some(format!("{}.html{}", stem, rest))
+        }
+        _ => None,
+    }
+}
+
+/// The part of a rendered page that is the chapter itself, i.e. what rustdoc
+/// put between the prelude and the postlude.
+pub fn page_content(html: &str) -> &str {
+    let start = html.find(CONTENT_START).map_or(0, |i| i + CONTENT_START.len());
+    let content = &html[start..];
+    &content[..content.find(CONTENT_END).unwrap_or(content.len())]
 }
 
-fn render(book: &Book, tgt: &Path) -> CliResult<()> {
+/// Write `contents` to the file `path`, creating the directories it is in.
+pub fn write_file(path: &Path, contents: &[u8]) -> CommandResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    File::create(path).and_then(|mut f| f.write_all(contents)).map_err(|e| Error::io(path, e))
+}
+
+/// The stylesheet of `theme`, for renderers that package it along with the
+/// chapters. It builds upon that of the Rust documentation, which is not
+/// part of the book, so the import of that is left out.
+pub fn stylesheet(theme: &Theme) -> String {
+    let css = String::from_utf8_lossy(&theme.css);
+    let lines: Vec<&str> = css.lines().filter(|line| !line.starts_with("@import")).collect();
+    lines.join("\n")
+}
+
+/// How to build a book.
+pub struct BuildOptions<'a> {
+    /// Appended verbatim to the end of every page.
+    pub extra_postlude: &'a str,
+    /// Whether draft chapters are an error.
+    pub strict: bool,
+    /// The chapters to render. When that is not all of them, the pages of
+    /// the others are left as they are.
+    pub only: Selection,
+    /// Whether to render pages that have not changed since the last build.
+    pub force: bool,
+    /// How many chapters to render at a time.
+    pub jobs: usize,
+}
+
+impl BuildOptions<'static> {
+    pub fn new() -> BuildOptions<'static> {
+        BuildOptions {
+            extra_postlude: "",
+            strict: false,
+            only: Selection::everything(),
+            force: false,
+            jobs: 1,
+        }
+    }
+}
+
+fn render(book: &Book,
+          config: &Config,
+          theme: &Theme,
+          options: &BuildOptions,
+          manifest: &mut Manifest,
+          term: &mut Term) -> CliResult<()> {
+    let src = &config.src;
+    let tgt = &config.build_dir;
     let tmp = TempDir::new("rustbook")?;
 
-    for (_section, item) in book.iter() {
-        let out_path = match item.path.parent() {
+    // the book's metadata goes into every page's <head>, before the theme's
+    let mut header_html = String::new();
+    if let Some(ref title) = config.title {
+        header_html.push_str(&format!("<meta name='application-name' content='{}'>\n",
+                                      markdown::escape_html(title)));
+    }
+    if !config.authors.is_empty() {
+        header_html.push_str(&format!("<meta name='author' content='{}'>\n",
+                                      markdown::escape_html(&config.authors.join(", "))));
+    }
+    if let Some(ref description) = config.description {
+        header_html.push_str(&format!("<meta name='description' content='{}'>\n",
+                                      markdown::escape_html(description)));
+    }
+    header_html.push_str(&format!("<meta http-equiv='content-language' content='{}'>\n",
+                                  markdown::escape_html(&config.language)));
+
+    // the chapters with pages, in order, for going from one to the next
+    let chapters: Vec<(String, &BookItem)> = book.iter()
+                                                 .filter(|&(_, item)| !item.is_draft())
+                                                 .collect();
+
+    let mut jobs = vec![];
+    let mut failed = vec![];
+    for (i, &(ref section, item)) in chapters.iter().enumerate() {
+        if !options.only.includes(section, item) { continue }
+
+        // the page, and the temporary files it is rendered from, are laid
+        // out like the chapter's source
+        let page = item.page();
+        let out_path = match page.parent() {
             Some(p) => tgt.join(p),
             None => tgt.to_path_buf(),
         };
 
-        let src;
-        if env::args().len() < 3 {
-            src = env::current_dir().unwrap().clone();
-        } else {
-            src = PathBuf::from(&env::args().nth(2).unwrap());
-        }
-        // preprocess the markdown, rerouting markdown references to html
-        // references
+        // preprocess the markdown, rerouting links to other chapters to
+        // their pages
         let mut markdown_data = String::new();
-        File::open(&src.join(&item.path)).and_then(|mut f| {
+        let read = File::open(&src.join(&item.path)).and_then(|mut f| {
             f.read_to_string(&mut markdown_data)
-        })?;
-        let preprocessed_path = tmp.path().join(item.path.file_name().unwrap());
-        {
-            let urls = markdown_data.replace(".md)", ".html)");
-            File::create(&preprocessed_path).and_then(|mut f| {
-                f.write_all(urls.as_bytes())
-            })?;
-        }
-
-        // write the prelude to a temporary HTML file for rustdoc inclusion
-        let prelude = tmp.path().join("prelude.html");
-        {
-            let mut buffer = BufWriter::new(File::create(&prelude)?);
-            writeln!(&mut buffer, r#"
-                <div id="nav">
-                    <button id="toggle-nav">
-                        <span class="sr-only">Toggle navigation</span>
-                        <span class="bar"></span>
-                        <span class="bar"></span>
-                        <span class="bar"></span>
-                    </button>
-                </div>"#)?;
-            let _ = write_toc(book, &item, &mut buffer);
-            writeln!(&mut buffer, "<div id='page-wrapper'>")?;
-            writeln!(&mut buffer, "<div id='page'>")?;
-        }
-
-        // write the postlude to a temporary HTML file for rustdoc inclusion
-        let postlude = tmp.path().join("postlude.html");
-        {
-            let mut buffer = BufWriter::new(File::create(&postlude)?);
-            writeln!(&mut buffer, "<script src='rustbook.js'></script>")?;
-            writeln!(&mut buffer, "</div></div>")?;
-        }
-
-        fs::create_dir_all(&out_path)?;
-
-        let rustdoc_args: &[String] = &[
+        });
+        if let Err(e) = read {
+            term.error(&format!("could not read {}: {}", item.path.display(), e));
+            failed.push(ChapterError {
+                path: item.path.clone(),
+                message: format!("could not be read: {}", e),
+            });
+            continue;
+        }
+        let urls = markdown::rewrite_links(&markdown_data, chapter_link);
+
+        // the page is laid out by the theme's templates, and split where
+        // the chapter goes into the prelude and postlude rustdoc includes
+        // before and after it
+        let mut toc = vec![];
+        let _ = write_toc(book, &item, &mut toc);
+        let mut context = Value::Map(vec![
+            ("book_title", Value::Text(config.book_title())),
+            ("language", Value::Text(config.language.clone())),
+            ("chapter_title", Value::Html(markdown::inline_to_html(&item.title))),
+            ("section", Value::Text(section.clone())),
+            ("path_to_root", Value::Text(item.path_to_root.display().to_string())),
+            ("prev", neighbour(if i > 0 { chapters.get(i - 1) } else { None }, item)),
+            ("next", neighbour(chapters.get(i + 1), item)),
+            ("toc", Value::Html(String::from_utf8(toc).unwrap())),
+        ]);
+        let mut head = header_html.clone();
+        if i > 0 {
+            head.push_str(&format!("<link rel='prev' href='{}'>\n",
+                                   markdown::escape_html(&page_link(item, chapters[i - 1].1))));
+        }
+        if let Some(&(_, next)) = chapters.get(i + 1) {
+            head.push_str(&format!("<link rel='next' href='{}'>\n",
+                                   markdown::escape_html(&page_link(item, next))));
+        }
+        head.push_str(&theme.render("head.html", &context)?);
+
+        if let Value::Map(ref mut fields) = context {
+            fields.push(("content", Value::Html(CONTENT_MARKER.to_string())));
+        }
+        let layout = theme.render("page.html", &context)?;
+        let mut parts = layout.split(CONTENT_MARKER);
+        let (prelude, rest) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(prelude), Some(rest), None) => (format!("{}{}\n", prelude, CONTENT_START), rest),
+            _ => {
+                return Err(Error::Config("the theme's page.html must show `{{content}}` \
+                                          exactly once".to_string()));
+            }
+        };
+        let prelude = prelude.as_bytes();
+        let mut postlude = vec![];
+        writeln!(&mut postlude, "{}", CONTENT_END)?;
+        writeln!(&mut postlude, "<script src='{}rustbook.js'></script>",
+                 markdown::escape_html(&item.path_to_root.display().to_string()))?;
+        writeln!(&mut postlude, "{}", options.extra_postlude)?;
+        write!(&mut postlude, "{}", rest)?;
+
+        let css = item.path_to_root.join("rustbook.css").display().to_string();
+        let hash = manifest::hash(&[urls.as_bytes(), head.as_bytes(), prelude,
+                                    &postlude, css.as_bytes(),
+                                    config.html.playground_url.as_bytes()]);
+        if !options.force && manifest.get(&page) == Some(&hash[..]) &&
+           tgt.join(&page).is_file() {
+            term.verbose(&format!("Skipping {} (unchanged)", item.path.display()));
+            continue;
+        }
+        term.verbose(&format!("Rendering {}", item.path.display()));
+
+        // every chapter gets temporary files of its own, as they are
+        // rendered at the same time
+        let preprocessed_path = tmp.path().join("src").join(&item.path);
+        write_file(&preprocessed_path, urls.as_bytes())?;
+        let head_path = preprocessed_path.with_extension("head.html");
+        write_file(&head_path, head.as_bytes())?;
+        let prelude_path = preprocessed_path.with_extension("prelude.html");
+        write_file(&prelude_path, prelude)?;
+        let postlude_path = preprocessed_path.with_extension("postlude.html");
+        write_file(&postlude_path, &postlude)?;
+
+        fs::create_dir_all(&out_path).map_err(|e| Error::io(&out_path, e))?;
+
+        let mut rustdoc_args = vec![
             "".to_string(),
             preprocessed_path.display().to_string(),
             format!("-o{}", out_path.display()),
-            format!("--html-before-content={}", prelude.display()),
-            format!("--html-after-content={}", postlude.display()),
-            format!("--markdown-playground-url=https://play.rust-lang.org/"),
-            format!("--markdown-css={}", item.path_to_root.join("rustbook.css").display()),
+            format!("--html-in-header={}", head_path.display()),
+            format!("--html-before-content={}", prelude_path.display()),
+            format!("--html-after-content={}", postlude_path.display()),
+            format!("--markdown-css={}", css),
             "--markdown-no-toc".to_string(),
         ];
-        let output_result = rustdoc::main_args(rustdoc_args);
-        if output_result != 0 {
-            let message = format!("Could not execute `rustdoc` with {:?}: {}",
-                                  rustdoc_args, output_result);
-            return Err(err(&message));
+        if !config.html.playground_url.is_empty() {
+            rustdoc_args.push(format!("--markdown-playground-url={}",
+                                      config.html.playground_url));
         }
+        // whatever happens, the page is out of date until rendered
+        manifest.remove(&page);
+        jobs.push((item.path.clone(), page, hash, rustdoc_args));
+    }
+
+    // execute rustdoc on the chapters that need it, then report the ones it
+    // failed on in the order of the book
+    let total = jobs.len();
+    let mut rendered = 0;
+    let results = pool::map(jobs, options.jobs, |(path, page, hash, rustdoc_args)| {
+        let output_result = rustdoc::main_args(&rustdoc_args);
+        (path, page, hash, rustdoc_args, output_result)
+    }, |&(ref path, ..)| {
+        rendered += 1;
+        term.progress(rendered, total, &format!("Rendered {}", path.display()));
+    });
+    term.clear_progress();
+    let results = match results {
+        Some(results) => results,
+        None => return Err(err("a thread rendering chapters died")),
+    };
+    for (path, page, hash, rustdoc_args, output_result) in results {
+        if output_result == 0 {
+            manifest.insert(page, hash);
+        } else {
+            term.error(&format!("could not render {}: `rustdoc` with {:?} failed: {}",
+                                path.display(), rustdoc_args, output_result));
+            failed.push(ChapterError {
+                path: path,
+                message: format!("`rustdoc` failed with {}", output_result),
+            });
+        }
+    }
+    if !failed.is_empty() {
+        return Err(Error::Render(failed));
     }
 
     // create index.html from the root README
-    fs::copy(&tgt.join("README.html"), &tgt.join("index.html"))?;
+    let readme = tgt.join("README.html");
+    if readme.is_file() {
+        let index = tgt.join("index.html");
+        fs::copy(&readme, &index).map_err(|e| Error::io(&index, e))?;
+    }
 
     Ok(())
 }
 
+/// Delete the pages of the chapters that are no longer in the book, or have
+/// become drafts.
+fn remove_stale_pages(book: &Book, config: &Config, manifest: &mut Manifest, term: &mut Term)
+                      -> io::Result<()> {
+    let pages: Vec<PathBuf> = book.iter()
+                                  .filter(|&(_, item)| !item.is_draft())
+                                  .map(|(_, item)| item.page())
+                                  .collect();
+    for page in manifest.pages() {
+        if pages.contains(&page) { continue }
+        term.verbose(&format!("Removing {}", page.display()));
+        manifest.remove(&page);
+        let path = config.build_dir.join(&page);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Build the book described by `config`. Pages that are already up to date,
+/// according to the manifest of the last build, are not rendered again.
+pub fn build(config: &Config, options: &BuildOptions, term: &mut Term) -> CommandResult<()> {
+    let tgt = &config.build_dir;
+    let manifest_path = tgt.join(MANIFEST_FILE);
+
+    // `_book` directory may already exist from previous runs. Unless it was
+    // built by us and can be built upon, delete it if it exists, but only
+    // if it is empty or was built by us, so that nothing else is lost.
+    let mut manifest = if !options.force && manifest_path.is_file() {
+        Manifest::load(&manifest_path)?
+    } else {
+        if options.only.is_everything() && tgt.is_dir() {
+            let mut entries = fs::read_dir(tgt).map_err(|e| Error::io(tgt, e))?;
+            if entries.next().is_some() && !manifest_path.is_file() {
+                return Err(Error::Config(format!("{} was not built by rustbook and is not \
+                                                  empty, so it is left alone; remove it \
+                                                  or build the book elsewhere",
+                                                 tgt.display())));
+            }
+            fs::remove_dir_all(tgt).map_err(|e| Error::io(tgt, e))?;
+        }
+        Manifest::new()
+    };
+    fs::create_dir_all(&tgt)?;
+
+    // Copy static files
+    let theme = Theme::load(config)?;
+    theme.copy_files(tgt)?;
+
+    let book = book::load(config, term)?;
+    if options.strict {
+        let drafts = book.iter().filter(|&(_, item)| item.is_draft()).collect::<Vec<_>>();
+        for &(ref section, item) in &drafts {
+            term.error(&format!("section {} '{}' is a draft", section, item.title));
+        }
+        if !drafts.is_empty() {
+            return Err(Error::Drafts(drafts.len()));
+        }
+    }
+
+    let only = &options.only;
+    if !only.is_everything() &&
+       !book.iter().any(|(section, item)| only.includes(&section, item)) {
+        return Err(Error::Usage("no chapters match the filters".to_string()));
+    }
+
+    // execute rustdoc on the whole book, recording what was rendered
+    // even if it could not be all of it
+    remove_stale_pages(&book, config, &mut manifest, term)?;
+    let result = render(&book, config, &theme, options, &mut manifest, term);
+    manifest.save(&manifest_path)?;
+    result?;
+
+    // index every page, whether rendered just now or before
+    search::write_index(&book, tgt)?;
+    Ok(())
+}
+
+/// Open `path` (a file or a URL) with the desktop's default application.
+pub fn open(path: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg("start").arg("");
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(path).spawn().map(|_| ())
+}
+
 impl Subcommand for Build {
-    fn parse_args(&mut self, _: &[String]) -> CliResult<()> {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, BUILD_OPTS])?;
+        self.book.parse(&mut matches)?;
+        self.dest_dir = matches.opt_str("dest-dir").map(PathBuf::from);
+        if !matches.free.is_empty() {
+            if self.dest_dir.is_some() {
+                return Err(err("the destination directory was given twice"));
+            }
+            self.dest_dir = Some(PathBuf::from(matches.free.remove(0)));
+        }
+        if let Some(arg) = matches.free.first() {
+            return Err(err(&format!("unexpected argument `{}`", arg)));
+        }
+        self.strict = matches.opt_present("strict");
+        self.open = matches.opt_present("open");
+        self.only = matches.opt_strs("only");
+        self.force = matches.opt_present("force");
+        if let Some(jobs) = matches.opt_str("jobs") {
+            self.jobs = parse_jobs(&jobs)?;
+        }
+        self.format = match matches.opt_str("format").as_ref().map(|f| &f[..]) {
+            None | Some("html") => Format::Html,
+            Some("epub") => Format::Epub,
+            Some("print") => Format::Print,
+            Some(format) => return Err(err(&format!("unknown format `{}`", format))),
+        };
+        if self.format != Format::Html && !self.only.is_empty() {
+            return Err(err("`--only` can only be used when building HTML pages"));
+        }
         Ok(())
     }
-    fn usage(&self) {}
+    fn usage(&self) {
+        print_usage("rustbook build [<src> [<dest>]] [options]",
+                    "Build the book in <src> (default: the current directory) into <dest>.",
+                    &[BOOK_OPTS, BUILD_OPTS]);
+    }
     fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
+        self.book.configure(term);
         let cwd = env::current_dir().unwrap();
-        let src;
-        let tgt;
+        let mut config = self.book.load_config()?;
+        if let Some(ref tgt) = self.dest_dir {
+            config.build_dir = cwd.join(tgt);
+        }
 
-        if env::args().len() < 3 {
-            src = cwd.clone();
-        } else {
-            src = PathBuf::from(&env::args().nth(2).unwrap());
+        let options = BuildOptions {
+            strict: self.strict,
+            only: Selection::new(&self.only, &[]),
+            force: self.force,
+            jobs: self.jobs,
+            ..BuildOptions::new()
+        };
+        let path = match self.format {
+            Format::Html => {
+                build(&config, &options, term)?;
+                config.build_dir.join("index.html")
+            }
+            Format::Epub => epub::build(&config, &options, term)?,
+            Format::Print => print::build(&config, &options, term)?,
+        };
+        term.info(&format!("Built the book in {}", path.parent().unwrap().display()));
+
+        if self.open {
+            open(&path.display().to_string())?;
         }
+        Ok(())
+    }
+}
 
-        if env::args().len() < 4 {
-            tgt = cwd.join("_book");
-        } else {
-            tgt = PathBuf::from(&env::args().nth(3).unwrap());
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use rustc_back::tempdir::TempDir;
+
+    use book::Selection;
+    use config::Config;
+    use error::Error;
+    use markdown;
+    use search::INDEX_FILE;
+    use term::{Term, Verbosity};
+    use super::{build, chapter_link, BuildOptions};
+
+    #[test]
+    fn chapter_links() {
+        let link = |url: &str| chapter_link(url).unwrap_or_else(|| format!("unchanged {}", url));
+        assert_eq!(link("intro.md"), "intro.html");
+        assert_eq!(link("foo.md#bar"), "foo.html#bar");
+        assert_eq!(link("foo.md?x=1#bar"), "foo.html?x=1#bar");
+        assert_eq!(link("dir/a.markdown#b"), "dir/a.html#b");
+        assert_eq!(link("../up.md"), "../up.html");
+        assert_eq!(link("https://example.com/a.md"), "unchanged https://example.com/a.md");
+        assert_eq!(link("mailto:a.md"), "unchanged mailto:a.md");
+        assert_eq!(link("/a.md"), "unchanged /a.md");
+        assert_eq!(link("#bar"), "unchanged #bar");
+        assert_eq!(link("picture.png"), "unchanged picture.png");
+        assert_eq!(link("a.md.txt"), "unchanged a.md.txt");
+        assert_eq!(link("dir/.md"), "unchanged dir/.md");
+        assert_eq!(link("v1.2/notes"), "unchanged v1.2/notes");
+    }
+
+    #[test]
+    fn rewrites_links_to_chapters() {
+        let doc = "See [the intro](intro.md#usage), [b][] and <c.markdown>.\n\n\
+                   [b]: https://example.com/b.md";
+        assert_eq!(markdown::rewrite_links(doc, chapter_link),
+                   "See [the intro](intro.html#usage), [b][] and [c.markdown](c.html).\n\n\
+                    [b]: https://example.com/b.md");
+    }
+
+    #[test]
+    fn only_into_an_empty_directory() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let files = [("SUMMARY.md", "* [A](a.md)\n* [B](b.md)\n"),
+                     ("README.md", "% Introduction\n\nHello.\n"),
+                     ("a.md", "% A\n\nThe first chapter.\n"),
+                     ("b.md", "% B\n\nThe second chapter.\n")];
+        for &(name, contents) in &files {
+            File::create(tmp.path().join(name)).unwrap().write_all(contents.as_bytes()).unwrap();
         }
+        let mut config = Config::default(tmp.path());
+        config.build_dir = tmp.path().join("out");
+        let options = BuildOptions {
+            only: Selection::new(&["a.md".to_string()], &[]),
+            ..BuildOptions::new()
+        };
+        let mut term = Term::new();
+        term.set_verbosity(Verbosity::Quiet);
 
-        // `_book` directory may already exist from previous runs. Check and
-        // delete it if it exists.
-        for entry in fs::read_dir(&cwd)? {
-            let path = entry?.path();
-            if path == tgt { fs::remove_dir_all(&tgt)? }
+        if let Err(e) = build(&config, &options, &mut term) {
+            panic!("{}", e);
         }
-        fs::create_dir(&tgt)?;
+        assert!(config.build_dir.join("a.html").is_file());
+        assert!(!config.build_dir.join("b.html").exists());
+        let mut index = String::new();
+        File::open(config.build_dir.join(INDEX_FILE)).unwrap()
+                                                     .read_to_string(&mut index).unwrap();
+        assert!(index.contains("\"page\":\"a.html\""), "{}", index);
+        assert!(!index.contains("\"page\":\"b.html\""), "{}", index);
+    }
 
-        // Copy static files
-        let css = include_bytes!("static/rustbook.css");
-        let js = include_bytes!("static/rustbook.js");
+    #[test]
+    fn only_wipes_its_own_build_directory() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let files = [("SUMMARY.md", "* [A](a.md)\n"),
+                     ("README.md", "% Introduction\n\nHello.\n"),
+                     ("a.md", "% A\n\nThe first chapter.\n"),
+                     ("out/notes.txt", "Not part of the book.\n")];
+        for &(name, contents) in &files {
+            let path = tmp.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+        }
+        let mut config = Config::default(tmp.path());
+        config.build_dir = tmp.path().join("out");
+        let mut term = Term::new();
+        term.set_verbosity(Verbosity::Quiet);
 
-        let mut css_file = File::create(tgt.join("rustbook.css"))?;
-        css_file.write_all(css)?;
+        // a directory holding other files is left alone
+        match build(&config, &BuildOptions::new(), &mut term) {
+            Err(Error::Config(ref message)) => {
+                assert!(message.contains("was not built by rustbook"), "{}", message)
+            }
+            Err(e) => panic!("{}", e),
+            Ok(()) => panic!("built into a directory holding other files"),
+        }
+        assert!(config.build_dir.join("notes.txt").is_file());
 
-        let mut js_file = File::create(tgt.join("rustbook.js"))?;
-        js_file.write_all(js)?;
+        // the book itself is not
+        fs::remove_file(config.build_dir.join("notes.txt")).unwrap();
+        if let Err(e) = build(&config, &BuildOptions::new(), &mut term) {
+            panic!("{}", e);
+        }
+        let options = BuildOptions { force: true, ..BuildOptions::new() };
+        if let Err(e) = build(&config, &options, &mut term) {
+            panic!("{}", e);
+        }
+        assert!(config.build_dir.join("a.html").is_file());
+    }
 
+    #[test]
+    fn renders_the_same_on_several_threads() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let mut summary = String::new();
+        for i in 0..8 {
+            summary.push_str(&format!("* [Chapter {}](c{}.md)\n", i, i));
+            let chapter = format!("% Chapter {}\n\n## Usage {}\n\nSee [the next](c{}.md).\n\n\
+                                   ```rust\nfn main() {{ println!(\"{}\"); }}\n```\n",
+                                  i, i, i + 1, i);
+            File::create(tmp.path().join(format!("c{}.md", i))).unwrap()
+                                                              .write_all(chapter.as_bytes())
+                                                              .unwrap();
+        }
+        File::create(tmp.path().join("SUMMARY.md")).unwrap()
+                                                   .write_all(summary.as_bytes()).unwrap();
+        File::create(tmp.path().join("README.md")).unwrap()
+                                                  .write_all(b"% Introduction\n\nHello.\n")
+                                                  .unwrap();
+        let mut term = Term::new();
+        term.set_verbosity(Verbosity::Quiet);
 
-        let mut summary = File::open(&src.join("SUMMARY.md"))?;
-        match book::parse_summary(&mut summary, &src) {
-            Ok(book) => {
-                // execute rustdoc on the whole book
-                render(&book, &tgt)
+        let mut pages = vec![];
+        for &jobs in &[1, 4] {
+            let mut config = Config::default(tmp.path());
+            config.build_dir = tmp.path().join(format!("out{}", jobs));
+            let options = BuildOptions { jobs: jobs, ..BuildOptions::new() };
+            if let Err(e) = build(&config, &options, &mut term) {
+                panic!("{}", e);
             }
-            Err(errors) => {
-                let n = errors.len();
-                for err in errors {
-                    term.err(&format!("error: {}", err)[..]);
-                }
-
-                Err(err(&format!("{} errors occurred", n)))
+            let mut contents = vec![];
+            for i in 0..8 {
+                let mut page = String::new();
+                File::open(config.build_dir.join(format!("c{}.html", i)))
+                    .unwrap().read_to_string(&mut page).unwrap();
+                contents.push(page);
             }
+            pages.push(contents);
         }
+        assert!(pages[1][3].contains("<h2 id='usage-3'"), "{}", pages[1][3]);
+        assert!(pages[0] == pages[1], "rendering on several threads changed the pages");
     }
 }
@@ -0,0 +1,604 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A book's configuration, read from an optional `book.toml` in its root
+//! directory:
+//!
+//! ```toml
+//! title = "The WhizBang Book"
+//! authors = ["Jane Doe"]
+//! language = "en"
+//! description = "All about WhizBang."
+//! src = "src"            # where SUMMARY.md and the chapters live
+//! build-dir = "_book"
+//! summary = "SUMMARY.md"
+//! introduction = "Introduction"
+//! theme = "theme"        # files overriding the built-in stylesheet, etc.
+//!
+//! [output.html]
+//! playground-url = "https://play.rust-lang.org/"
+//!
+//! [output.epub]
+//! identifier = "urn:isbn:9780000000000"
+//!
+//! [output.print]
+//! page-break = true      # start every chapter on a new sheet of paper
+//! ```
+//!
+//! Paths are relative to the directory containing `book.toml`. Only the
+//! subset of TOML needed for the above is understood.
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use error::{CliResult, Error};
+
+pub const CONFIG_FILE: &'static str = "book.toml";
+
+/// Settings for the HTML renderer, from `[output.html]`.
+#[derive(Clone)]
+pub struct HtmlConfig {
+    /// Where the "Run" buttons of code examples send the code. Empty to
+    /// leave the buttons out.
+    pub playground_url: String,
+}
+
+/// Settings for the EPUB renderer, from `[output.epub]`.
+#[derive(Clone)]
+pub struct EpubConfig {
+    /// The identifier e-readers tell the book apart by, such as a URN with
+    /// its ISBN. One is made from the title and authors if there is none.
+    pub identifier: Option<String>,
+}
+
+/// Settings for the print renderer, from `[output.print]`.
+#[derive(Clone)]
+pub struct PrintConfig {
+    /// Whether every chapter starts on a new sheet of paper.
+    pub page_break: bool,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub language: String,
+    pub description: Option<String>,
+    /// The directory containing the summary and the chapters.
+    pub src: PathBuf,
+    /// The directory the book is built into.
+    pub build_dir: PathBuf,
+    /// The summary's file name, relative to `src`.
+    pub summary: PathBuf,
+    /// The title of the `README.md` chapter every book starts with.
+    pub introduction: String,
+    /// The directory of the book's theme, which need not exist.
+    pub theme_dir: PathBuf,
+    pub html: HtmlConfig,
+    pub epub: EpubConfig,
+    pub print: PrintConfig,
+}
+
+impl Config {
+    /// The configuration of a book without a `book.toml`.
+    pub fn default(root: &Path) -> Config {
+        let cwd = env::current_dir().unwrap();
+        Config {
+            title: None,
+            authors: vec![],
+            language: "en".to_string(),
+            description: None,
+            src: root.to_path_buf(),
+            build_dir: cwd.join("_book"),
+            summary: PathBuf::from("SUMMARY.md"),
+            introduction: "Introduction".to_string(),
+            theme_dir: root.join("theme"),
+            html: HtmlConfig {
+                playground_url: "https://play.rust-lang.org/".to_string(),
+            },
+            epub: EpubConfig { identifier: None },
+            print: PrintConfig { page_break: true },
+        }
+    }
+
+    /// Load the configuration of the book in `root`, from its `book.toml`
+    /// if there is one.
+    pub fn load(root: &Path) -> CliResult<Config> {
+        let path = root.join(CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Config::default(root));
+        }
+        Config::from_file(&path, root)
+    }
+
+    /// Load the configuration in `path`, for the book in `root`. Paths in the
+    /// file are relative to the directory containing it.
+    pub fn from_file(path: &Path, root: &Path) -> CliResult<Config> {
+        let base = path.parent().unwrap_or(root);
+        let mut input = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut input))
+                        .map_err(|e| Error::io(path, e))?;
+
+        let entries = match parse(&input) {
+            Ok(entries) => entries,
+            Err((line, message)) => {
+                return Err(Error::Config(format!("{}:{}: {}", path.display(), line, message)));
+            }
+        };
+
+        let mut config = Config::default(root);
+        let mut seen: Vec<String> = vec![];
+        for (key, value, line) in entries {
+            if seen.contains(&key) {
+                return Err(Error::Config(format!("{}:{}: duplicate key `{}`",
+                                                 path.display(), line, key)));
+            }
+            let result = match &key[..] {
+                "title" => value.into_string().map(|s| config.title = Some(s)),
+                "authors" => value.into_strings().map(|s| config.authors = s),
+                "language" => value.into_string().map(|s| config.language = s),
+                "description" => value.into_string().map(|s| config.description = Some(s)),
+                "src" => value.into_string().map(|s| config.src = base.join(s)),
+                "build-dir" => value.into_string().map(|s| config.build_dir = base.join(s)),
+                "summary" => value.into_string().map(|s| config.summary = PathBuf::from(s)),
+                "introduction" => value.into_string().map(|s| config.introduction = s),
+                "theme" => value.into_string().map(|s| config.theme_dir = base.join(s)),
+                "output.html.playground-url" => {
+                    value.into_string().map(|s| config.html.playground_url = s)
+                }
+                "output.epub.identifier" => {
+                    value.into_string().map(|s| config.epub.identifier = Some(s))
+                }
+                "output.print.page-break" => {
+                    value.into_bool().map(|b| config.print.page_break = b)
+                }
+                _ => Err(format!("unknown key `{}`", key)),
+            };
+            if let Err(message) = result {
+                return Err(Error::Config(format!("{}:{}: {}", path.display(), line, message)));
+            }
+            seen.push(key);
+        }
+
+        // the build directory is emptied before every full build, so it
+        // must not hold the book itself
+        let build_dir = clean_path(&config.build_dir);
+        for dir in &[&config.src, root, base] {
+            if clean_path(dir).starts_with(&build_dir) {
+                return Err(Error::Config(format!("{}: `build-dir` {} would contain {}, \
+                                                  whose files a build would delete",
+                                                 path.display(), config.build_dir.display(),
+                                                 dir.display())));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The title of the book, or the name of its directory if it has none.
+    pub fn book_title(&self) -> String {
+        match self.title {
+            Some(ref title) => title.clone(),
+            None => self.src.file_name().map_or("Untitled".to_string(), |name| {
+                name.to_string_lossy().into_owned()
+            }),
+        }
+    }
+
+    /// The path of the book's summary.
+    pub fn summary_path(&self) -> PathBuf {
+        self.src.join(&self.summary)
+    }
+}
+
+/// `path` with its `.` and `..` components resolved, without looking at
+/// the file system.
+fn clean_path(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !cleaned.pop() {
+                    cleaned.push("..");
+                }
+            }
+            other => cleaned.push(other.as_os_str()),
+        }
+    }
+    cleaned
+}
+
+#[derive(Debug)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match *self {
+            Value::String(..) => "a string",
+            Value::Integer(..) => "an integer",
+            Value::Boolean(..) => "a boolean",
+            Value::Array(..) => "an array",
+        }
+    }
+
+    fn into_string(self) -> Result<String, String> {
+        match self {
+            Value::String(s) => Ok(s),
+            other => Err(format!("expected a string, found {}", other.type_name())),
+        }
+    }
+
+    fn into_bool(self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(b) => Ok(b),
+            other => Err(format!("expected a boolean, found {}", other.type_name())),
+        }
+    }
+
+    fn into_strings(self) -> Result<Vec<String>, String> {
+        match self {
+            Value::Array(values) => values.into_iter().map(|v| v.into_string()).collect(),
+            other => Err(format!("expected an array of strings, found {}", other.type_name())),
+        }
+    }
+}
+
+/// Parse TOML into a list of dotted keys, their values and the lines they
+/// were found on. Errors carry the line they happened on.
+fn parse(input: &str) -> Result<Vec<(String, Value, usize)>, (usize, String)> {
+    let mut parser = Parser { input: input, pos: 0, line: 1 };
+    let mut table = String::new();
+    let mut entries = vec![];
+
+    loop {
+        parser.skip_whitespace_and_comments(true);
+        match parser.peek() {
+            None => return Ok(entries),
+            Some('[') => {
+                parser.bump();
+                if parser.peek() == Some('[') {
+                    return Err(parser.error("arrays of tables are not supported"));
+                }
+                parser.skip_whitespace_and_comments(false);
+                table = parser.key()?;
+                parser.skip_whitespace_and_comments(false);
+                parser.expect(']')?;
+            }
+            Some(_) => {
+                let line = parser.line;
+                let key = parser.key()?;
+                parser.skip_whitespace_and_comments(false);
+                parser.expect('=')?;
+                parser.skip_whitespace_and_comments(false);
+                let value = parser.value()?;
+                let key = if table.is_empty() { key } else { format!("{}.{}", table, key) };
+                entries.push((key, value, line));
+            }
+        }
+
+        // whatever was on the line has to be the end of it
+        parser.skip_whitespace_and_comments(false);
+        match parser.peek() {
+            None | Some('\n') => {}
+            Some(c) => return Err(parser.error(&format!("unexpected `{}`", c))),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+            if c == '\n' { self.line += 1 }
+        }
+        c
+    }
+
+    fn error(&self, message: &str) -> (usize, String) {
+        (self.line, message.to_string())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), (usize, String)> {
+        // errors are on the line of what was found, even a line break
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some('\n') => Err(self.error(&format!("expected `{}`, found the end of the line",
+                                                  expected))),
+            Some(c) => Err(self.error(&format!("expected `{}`, found `{}`", expected, c))),
+            None => Err(self.error(&format!("expected `{}`, found the end of the file",
+                                            expected))),
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self, newlines: bool) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') => { self.bump(); }
+                Some('\n') if newlines => { self.bump(); }
+                Some('#') => {
+                    while self.peek().map_or(false, |c| c != '\n') { self.bump(); }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// A possibly dotted key, made of bare or quoted parts.
+    fn key(&mut self) -> Result<String, (usize, String)> {
+        let mut key = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => key.push_str(&self.basic_string()?),
+                Some('\'') => key.push_str(&self.literal_string()?),
+                _ => {
+                    let start = self.pos;
+                    while self.peek().map_or(false, |c| c.is_alphanumeric() || c == '_' ||
+                                                        c == '-') {
+                        self.bump();
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+                    key.push_str(&self.input[start..self.pos]);
+                }
+            }
+            self.skip_whitespace_and_comments(false);
+            if self.peek() != Some('.') { return Ok(key) }
+            self.bump();
+            self.skip_whitespace_and_comments(false);
+            key.push('.');
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, (usize, String)> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut values = vec![];
+                loop {
+                    self.skip_whitespace_and_comments(true);
+                    if self.peek() == Some(']') { break }
+                    values.push(self.value()?);
+                    self.skip_whitespace_and_comments(true);
+                    match self.peek() {
+                        Some(',') => { self.bump(); }
+                        _ => break,
+                    }
+                }
+                self.expect(']')?;
+                Ok(Value::Array(values))
+            }
+            Some('{') => Err(self.error("inline tables are not supported")),
+            _ => {
+                let start = self.pos;
+                while self.peek().map_or(false, |c| c.is_alphanumeric() || c == '_' ||
+                                                    c == '-' || c == '+') {
+                    self.bump();
+                }
+                let word = &self.input[start..self.pos];
+                match word {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => match word.replace("_", "").parse() {
+                        Ok(n) => Ok(Value::Integer(n)),
+                        Err(..) if word.is_empty() => Err(self.error("expected a value")),
+                        Err(..) => Err(self.error(&format!("invalid value `{}`", word))),
+                    },
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, (usize, String)> {
+        self.expect('"')?;
+        if self.input[self.pos..].starts_with("\"\"") {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+        let mut s = String::new();
+        loop {
+            if self.peek() == Some('\n') {
+                return Err(self.error("unterminated string"));
+            }
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c @ 'u') | Some(c @ 'U') => {
+                            let len = if c == 'u' { 4 } else { 8 };
+                            let start = self.pos;
+                            for _ in 0..len { self.bump(); }
+                            let code = u32::from_str_radix(&self.input[start..self.pos], 16)
+                                .ok()
+                                .and_then(::std::char::from_u32);
+                            match code {
+                                Some(c) => c,
+                                None => return Err(self.error("invalid unicode escape")),
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape in string")),
+                    };
+                    s.push(escaped);
+                }
+                None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, (usize, String)> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            if self.peek() == Some('\n') {
+                return Err(self.error("unterminated string"));
+            }
+            match self.bump() {
+                Some('\'') => return Ok(s),
+                None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::path::Path;
+
+    use rustc_back::tempdir::TempDir;
+
+    use super::{parse, Config, Value};
+
+    fn parse_ok(input: &str) -> Vec<(String, Value, usize)> {
+        match parse(input) {
+            Ok(entries) => entries,
+            Err((line, message)) => panic!("{}: {}", line, message),
+        }
+    }
+
+    fn string(value: &Value) -> &str {
+        match *value {
+            Value::String(ref s) => s,
+            ref other => panic!("expected a string, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keys_and_tables() {
+        let entries = parse_ok("title = \"A\" # comment\n\n[output.html]\n\
+                                playground-url = 'B'\n[ \"a b\" . c ]\nd.e = \"F\"\n");
+        let keys: Vec<_> = entries.iter().map(|&(ref key, _, line)| (&key[..], line)).collect();
+        assert_eq!(keys, [("title", 1), ("output.html.playground-url", 4), ("a b.c.d.e", 6)]);
+        assert_eq!(string(&entries[1].1), "B");
+    }
+
+    #[test]
+    fn values() {
+        let entries = parse_ok("a = \"\\\"\\\\\\n\\u00e9\"\nb = 'C:\\path'\nc = [\"x\",\n  'y', ]\n\
+                                d = true\ne = 1_000\nf = -3\ng = []");
+        assert_eq!(string(&entries[0].1), "\"\\\n\u{e9}");
+        assert_eq!(string(&entries[1].1), "C:\\path");
+        match entries[2].1 {
+            Value::Array(ref values) => {
+                let values: Vec<_> = values.iter().map(string).collect();
+                assert_eq!(values, ["x", "y"]);
+            }
+            ref other => panic!("expected an array, found {:?}", other),
+        }
+        match (&entries[3].1, &entries[4].1, &entries[5].1) {
+            (&Value::Boolean(true), &Value::Integer(1000), &Value::Integer(-3)) => {}
+            other => panic!("{:?}", other),
+        }
+        match entries[6].1 {
+            Value::Array(ref values) => assert!(values.is_empty()),
+            ref other => panic!("expected an array, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = |input: &str| parse(input).err().expect(input);
+        assert_eq!(error("a = \"b"), (1, "unterminated string".to_string()));
+        assert_eq!(error("a = 'b\nc = 1"), (1, "unterminated string".to_string()));
+        assert_eq!(error("\n\na = b"), (3, "invalid value `b`".to_string()));
+        assert_eq!(error("a = 1 b = 2"), (1, "unexpected `b`".to_string()));
+        assert_eq!(error("a \"b\""), (1, "expected `=`, found `\"`".to_string()));
+        assert_eq!(error("[a\nb = 1"), (1, "expected `]`, found the end of the line".to_string()));
+        assert_eq!(error("a = [1, 2"), (1, "expected `]`, found the end of the file".to_string()));
+        assert_eq!(error("[[a]]"), (1, "arrays of tables are not supported".to_string()));
+        assert_eq!(error("a = {}"), (1, "inline tables are not supported".to_string()));
+        assert_eq!(error("a = \"\"\"b\"\"\""),
+                   (1, "multi-line strings are not supported".to_string()));
+        assert_eq!(error("a = \"\\q\""), (1, "invalid escape in string".to_string()));
+        assert_eq!(error("= 1"), (1, "expected a key".to_string()));
+    }
+
+    #[test]
+    fn unknown_and_duplicate_keys() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let dir = tmp.path();
+        let path = dir.join("book.toml");
+        let load = |input: &str| {
+            File::create(&path).unwrap().write_all(input.as_bytes()).unwrap();
+            Config::from_file(&path, Path::new("/book"))
+        };
+
+        let config = load("title = \"T\"\nsrc = \"src\"\n[output.html]\nplayground-url = \"\"\n\
+                           [output.epub]\nidentifier = \"urn:isbn:0\"\n\
+                           [output.print]\npage-break = false").ok().unwrap();
+        assert_eq!(config.title, Some("T".to_string()));
+        assert_eq!(config.src, dir.join("src"));
+        assert_eq!(config.html.playground_url, "");
+        assert_eq!(config.epub.identifier, Some("urn:isbn:0".to_string()));
+        assert!(!config.print.page_break);
+
+        let message = load("titel = \"T\"").err().unwrap().to_string();
+        assert!(message.ends_with("book.toml:1: unknown key `titel`"), "{}", message);
+        let message = load("title = \"T\"\ntitle = \"U\"").err().unwrap().to_string();
+        assert!(message.ends_with("book.toml:2: duplicate key `title`"), "{}", message);
+        let message = load("[output.print]\npage-break = 1").err().unwrap().to_string();
+        assert!(message.ends_with("book.toml:2: expected a boolean, found an integer"),
+                "{}", message);
+        let message = load("authors = \"A\"").err().unwrap().to_string();
+        assert!(message.ends_with("expected an array of strings, found a string"), "{}", message);
+    }
+
+    #[test]
+    fn build_dir_outside_the_book() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let root = tmp.path().join("book");
+        let path = root.join("book.toml");
+        fs::create_dir_all(&root).unwrap();
+        let load = |input: &str| {
+            File::create(&path).unwrap().write_all(input.as_bytes()).unwrap();
+            Config::from_file(&path, &root)
+        };
+
+        for build_dir in &[".", "..", "./", "src", "src/..", "../.."] {
+            let input = format!("src = \"src\"\nbuild-dir = \"{}\"", build_dir);
+            let message = load(&input).err().expect(build_dir).to_string();
+            assert!(message.contains("whose files a build would delete"), "{}", message);
+        }
+        for build_dir in &["_book", "../out", "src/../_book"] {
+            let input = format!("src = \"src\"\nbuild-dir = \"{}\"", build_dir);
+            assert!(load(&input).is_ok(), "{}", build_dir);
+        }
+    }
+}
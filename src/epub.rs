@@ -0,0 +1,316 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The EPUB renderer, which packages the pages rendered for the web, along
+//! with the stylesheet and the pictures they show, as an EPUB 3 book.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rustc_back::tempdir::TempDir;
+
+use book;
+use build::{self, BuildOptions};
+use config::Config;
+use error::{CommandResult, Error};
+use html;
+use markdown;
+use term::Term;
+use theme::Theme;
+use zip::ZipWriter;
+
+/// The name of the EPUB file in the build directory.
+pub const EPUB_FILE: &'static str = "book.epub";
+
+const CONTAINER: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+/// Build the book described by `config` as an EPUB file in its build
+/// directory, and return the file's path. Of `options`, only `strict` and
+/// `jobs` apply.
+pub fn build(config: &Config, options: &BuildOptions, term: &mut Term) -> CommandResult<PathBuf> {
+    // render the pages as for the web, but without "Run" buttons, which
+    // e-readers could do little with
+    let tmp = TempDir::new("rustbook")?;
+    let mut html_config = config.clone();
+    html_config.build_dir = tmp.path().join("html");
+    html_config.html.playground_url = String::new();
+    let html_options = BuildOptions {
+        strict: options.strict,
+        jobs: options.jobs,
+        ..BuildOptions::new()
+    };
+    build::build(&html_config, &html_options, term)?;
+
+    let book = book::load(config, term)?;
+    let title = config.book_title();
+
+    let now = now();
+    // the archive is put together in memory, and only then written out
+    let mut zip = ZipWriter::new(vec![], now);
+    // the mimetype must come first, so that the file can be recognized by it
+    zip.add("mimetype", b"application/epub+zip")?;
+    zip.add("META-INF/container.xml", CONTAINER.as_bytes())?;
+
+    // every page is a content document, laid out as in the HTML build so
+    // that links between them still work
+    let mut items = String::new();
+    let mut spine = String::new();
+    let mut images: Vec<PathBuf> = vec![];
+    let pages = book.iter().map(|(_, item)| item).filter(|item| !item.is_draft());
+    for (i, item) in pages.enumerate() {
+        let page = item.page();
+        term.verbose(&format!("Packaging {}", item.path.display()));
+        let page_path = html_config.build_dir.join(&page);
+        let mut html = String::new();
+        File::open(&page_path).and_then(|mut f| f.read_to_string(&mut html))
+                              .map_err(|e| Error::io(&page_path, e))?;
+        let mut sources = vec![];
+        let body = html::to_xhtml(build::page_content(&html), |element, name, value| {
+            if element == "img" && name == "src" {
+                sources.push(value.to_string());
+            }
+            None
+        });
+
+        let document = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                <!DOCTYPE html>\n\
+                                <html xmlns=\"http://www.w3.org/1999/xhtml\" \
+                                xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+                                lang=\"{lang}\" xml:lang=\"{lang}\">\n\
+                                <head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n\
+                                <link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/>\n\
+                                </head>\n<body>\n{}\n</body>\n</html>\n",
+                               markdown::escape_html(&markdown::inline_to_text(&item.title)),
+                               url(&item.path_to_root.join("rustbook.css")),
+                               body.trim(),
+                               lang = markdown::escape_html(&config.language));
+        zip.add(&format!("OEBPS/{}", archive_path(&page)), document.as_bytes())?;
+        items.push_str(&format!("<item id=\"page-{}\" href=\"{}\" \
+                                 media-type=\"application/xhtml+xml\"/>\n",
+                                i, markdown::escape_html(&url(&page))));
+        spine.push_str(&format!("<itemref idref=\"page-{}\"/>\n", i));
+
+        // the pictures on the page go along with it, if they are part of
+        // the book
+        let dir = item.path.parent().unwrap_or(Path::new(""));
+        for source in sources {
+            let end = source.find(|c| c == '#' || c == '?').unwrap_or(source.len());
+            let file = match book::normalize(&dir.join(&source[..end])) {
+                Some(ref file) if !markdown::has_scheme(&source) && !source.starts_with('/') &&
+                                  config.src.join(file).is_file() => file.clone(),
+                _ => {
+                    term.warn(&format!("{} shows `{}`, which is not a file of the \
+                                        book, so it is left out", item.path.display(), source));
+                    continue;
+                }
+            };
+            if media_type(&file).is_none() {
+                term.warn(&format!("{} shows `{}`, which is not a picture EPUB \
+                                    supports, so it is left out", item.path.display(), source));
+            } else if !images.contains(&file) {
+                images.push(file);
+            }
+        }
+    }
+
+    for (i, image) in images.iter().enumerate() {
+        let image_path = config.src.join(image);
+        let mut data = vec![];
+        File::open(&image_path).and_then(|mut f| f.read_to_end(&mut data))
+                               .map_err(|e| Error::io(&image_path, e))?;
+        zip.add(&format!("OEBPS/{}", archive_path(image)), &data)?;
+        items.push_str(&format!("<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                                i, markdown::escape_html(&url(image)),
+                                media_type(image).unwrap()));
+    }
+
+    zip.add("OEBPS/rustbook.css", build::stylesheet(&Theme::load(config)?).as_bytes())?;
+
+    let mut toc = String::new();
+    build::toc_items(&book.chapters, "", &|item| url(&item.page()), &mut toc);
+    let nav = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                       <!DOCTYPE html>\n\
+                       <html xmlns=\"http://www.w3.org/1999/xhtml\" \
+                       xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+                       lang=\"{lang}\" xml:lang=\"{lang}\">\n\
+                       <head>\n<meta charset=\"utf-8\"/>\n<title>{title}</title>\n\
+                       <link rel=\"stylesheet\" type=\"text/css\" href=\"rustbook.css\"/>\n\
+                       </head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\">\n\
+                       <h1>{title}</h1>\n<ol class=\"chapter\">\n{}</ol>\n</nav>\n\
+                       </body>\n</html>\n",
+                      toc,
+                      title = markdown::escape_html(&title),
+                      lang = markdown::escape_html(&config.language));
+    zip.add("OEBPS/nav.xhtml", nav.as_bytes())?;
+
+    let mut metadata = String::new();
+    for author in &config.authors {
+        metadata.push_str(&format!("<dc:creator>{}</dc:creator>\n",
+                                   markdown::escape_html(author)));
+    }
+    if let Some(ref description) = config.description {
+        metadata.push_str(&format!("<dc:description>{}</dc:description>\n",
+                                   markdown::escape_html(description)));
+    }
+    // readers tell books apart by their identifier, which should stay the
+    // same from one edition of a book to the next, and from one version of
+    // rustbook to the next
+    let identifier = match config.epub.identifier {
+        Some(ref identifier) => identifier.clone(),
+        None => {
+            let key = format!("{}\0{}", title, config.authors.join("\n"));
+            format!("urn:rustbook:{:016x}", fnv1a(key.as_bytes()))
+        }
+    };
+    let (year, month, day, hour, minute, second) = now;
+    let package = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                           <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+                           unique-identifier=\"book-id\" xml:lang=\"{lang}\">\n\
+                           <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+                           <dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+                           <dc:title>{}</dc:title>\n\
+                           <dc:language>{lang}</dc:language>\n\
+                           {}\
+                           <meta property=\"dcterms:modified\">\
+                           {:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z</meta>\n\
+                           </metadata>\n\
+                           <manifest>\n\
+                           <item id=\"nav\" href=\"nav.xhtml\" \
+                           media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+                           <item id=\"css\" href=\"rustbook.css\" media-type=\"text/css\"/>\n\
+                           {}</manifest>\n\
+                           <spine>\n{}</spine>\n\
+                           </package>\n",
+                          markdown::escape_html(&identifier), markdown::escape_html(&title),
+                          metadata,
+                          year, month, day, hour, minute, second, items, spine,
+                          lang = markdown::escape_html(&config.language));
+    zip.add("OEBPS/content.opf", package.as_bytes())?;
+
+    let path = config.build_dir.join(EPUB_FILE);
+    build::write_file(&path, &zip.finish()?)?;
+    Ok(path)
+}
+
+/// The media type of a picture, by its extension, if it is one that EPUB
+/// readers must support.
+fn media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_ref().map(|e| &e[..]) {
+        Some("png") => Some("image/png"),
+        Some("jpg") | Some("jpeg") => Some("image/jpeg"),
+        Some("gif") => Some("image/gif"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("webp") => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// The name of the file `path` in the archive, with `/` between components.
+fn archive_path(path: &Path) -> String {
+    path.iter().map(|part| part.to_string_lossy().into_owned()).collect::<Vec<_>>().join("/")
+}
+
+/// A relative URL of the file `path`.
+fn url(path: &Path) -> String {
+    let mut url = String::new();
+    for byte in archive_path(path).bytes() {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// The 64-bit FNV-1a hash of `data`, which unlike `DefaultHasher` is the
+/// same on every platform and with every version of Rust.
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// The current time in UTC, as (year, month, day, hour, minute, second).
+fn now() -> (u32, u32, u32, u32, u32, u32) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // the date of a number of days since 1970-01-01, from Howard Hinnant's
+    // `civil_from_days`
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year as u32, month as u32, day as u32,
+     (secs / 3600) as u32, (secs / 60 % 60) as u32, (secs % 60) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::prelude::*;
+    use rustc_back::tempdir::TempDir;
+
+    use build::BuildOptions;
+    use config::Config;
+    use term::{Term, Verbosity};
+    use super::{build, fnv1a};
+
+    #[test]
+    fn mimetype_comes_first_and_stored() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let files = [("SUMMARY.md", "* [A](a.md)\n"),
+                     ("README.md", "% Introduction\n\nHello.\n"),
+                     ("a.md", "% A\n\nThe first chapter.\n")];
+        for &(name, contents) in &files {
+            File::create(tmp.path().join(name)).unwrap().write_all(contents.as_bytes()).unwrap();
+        }
+        let mut config = Config::default(tmp.path());
+        config.build_dir = tmp.path().join("out");
+        let mut term = Term::new();
+        term.set_verbosity(Verbosity::Quiet);
+
+        let path = match build(&config, &BuildOptions::new(), &mut term) {
+            Ok(path) => path,
+            Err(e) => panic!("{}", e),
+        };
+        let mut data = vec![];
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        // the local header of the first file, which is stored uncompressed
+        assert_eq!(&data[..4], b"PK\x03\x04");
+        assert_eq!(&data[8..10], b"\0\0");
+        assert_eq!(&data[26..30], b"\x08\0\0\0");
+        assert_eq!(&data[30..58], &b"mimetypeapplication/epub+zip"[..]);
+    }
+
+    #[test]
+    fn fnv1a_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
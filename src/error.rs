@@ -8,29 +8,171 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Error handling utilities. WIP.
+//! The errors of rustbook, and the exit status each kind of them ends it
+//! with, so that scripts can tell them apart:
+//!
+//! | Status | Meaning                                                                   |
+//! |--------|---------------------------------------------------------------------------|
+//! | 0      | Success                                                                   |
+//! | 1      | Any other error                                                           |
+//! | 2      | The command line could not be understood, or its filters match no chapter |
+//! | 3      | `book.toml` or the theme is not valid                                     |
+//! | 4      | `SUMMARY.md` is not valid, or has drafts left under `build --strict`      |
+//! | 5      | A file could not be read or written, such as a missing `SUMMARY.md`       |
+//! | 6      | rustdoc could not render some chapters                                    |
+//! | 7      | Some examples of `rustbook test` failed                                   |
+//! | 8      | `rustbook linkcheck` found broken links                                   |
 
-use std::error::Error;
+use std::error;
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
-pub type CliError = Box<Error + 'static>;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_SUMMARY: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+pub const EXIT_RENDER: i32 = 6;
+pub const EXIT_TEST: i32 = 7;
+pub const EXIT_LINKS: i32 = 8;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed, if known which.
+    Io { path: Option<PathBuf>, error: io::Error },
+    /// The configuration in `book.toml`, or the theme, is not valid.
+    Config(String),
+    /// The command line asks for something that cannot be done, such as
+    /// filters that no chapter matches.
+    Usage(String),
+    /// The summary had this many errors, which have been reported already.
+    Summary(usize),
+    /// `build --strict` found this many draft chapters, which have been
+    /// reported already.
+    Drafts(usize),
+    /// rustdoc could not render these chapters, which have been reported
+    /// already.
+    Render(Vec<ChapterError>),
+    /// These of a number of chapters failed their tests.
+    Test { failed: Vec<ChapterError>, total: usize },
+    /// `linkcheck` found this many problems.
+    Links(usize),
+    Other(String),
+}
+
+/// Why a single chapter could not be rendered or tested.
+#[derive(Debug)]
+pub struct ChapterError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl error::Error for ChapterError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ChapterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+pub type CliError = Error;
 pub type CliResult<T> = Result<T, CliError>;
 
-pub type CommandError = Box<Error + 'static>;
+pub type CommandError = Error;
 pub type CommandResult<T> = Result<T, CommandError>;
 
-pub fn err(s: &str) -> CliError {
-    #[derive(Debug)]
-    struct E(String);
+impl Error {
+    /// An error reading or writing the file `path`.
+    pub fn io(path: &Path, error: io::Error) -> Error {
+        Error::Io { path: Some(path.to_path_buf()), error: error }
+    }
 
-    impl Error for E {
-        fn description(&self) -> &str { &self.0 }
+    /// The status rustbook exits with because of this error.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Io { .. } => EXIT_IO,
+            Error::Usage(..) => EXIT_USAGE,
+            Error::Config(..) => EXIT_CONFIG,
+            Error::Summary(..) | Error::Drafts(..) => EXIT_SUMMARY,
+            Error::Render(..) => EXIT_RENDER,
+            Error::Test { .. } => EXIT_TEST,
+            Error::Links(..) => EXIT_LINKS,
+            Error::Other(..) => EXIT_FAILURE,
+        }
     }
-    impl fmt::Display for E {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            self.0.fmt(f)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io { .. } => "I/O error",
+            Error::Usage(ref message) |
+            Error::Config(ref message) |
+            Error::Other(ref message) => message,
+            Error::Summary(..) => "invalid summary",
+            Error::Drafts(..) => "draft chapters remain",
+            Error::Render(..) => "chapters could not be rendered",
+            Error::Test { .. } => "chapters failed their tests",
+            Error::Links(..) => "broken links",
         }
     }
 
-    Box::new(E(s.to_string()))
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io { ref error, .. } => Some(error),
+            // the first of them, as they have all been reported
+            Error::Render(ref failed) | Error::Test { ref failed, .. } => {
+                failed.first().map(|e| e as &error::Error)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The message of the error itself, without its cause.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io { path: Some(ref path), .. } => path.display().fmt(f),
+            Error::Io { path: None, .. } => "I/O error".fmt(f),
+            Error::Usage(ref message) |
+            Error::Config(ref message) |
+            Error::Other(ref message) => message.fmt(f),
+            Error::Summary(n) => write!(f, "{} errors occurred in the summary", n),
+            Error::Drafts(n) => write!(f, "{} draft chapters remain", n),
+            Error::Render(ref failed) => {
+                write!(f, "{} chapters could not be rendered", failed.len())
+            }
+            Error::Test { ref failed, total } => {
+                write!(f, "{} of {} chapters failed", failed.len(), total)
+            }
+            Error::Links(n) => write!(f, "{} problems found", n),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io { path: None, error: error }
+    }
+}
+
+/// The message of `error`, followed by those of its causes.
+pub fn describe(error: &error::Error) -> String {
+    let mut message = error.to_string();
+    let mut cause = error.cause();
+    while let Some(error) = cause {
+        message.push_str(&format!(": {}", error));
+        cause = error.cause();
+    }
+    message
+}
+
+pub fn err(s: &str) -> CliError {
+    Error::Other(s.to_string())
 }
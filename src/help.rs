@@ -8,29 +8,51 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Implementation of the `help` subcommand. Currently just prints basic usage info.
+//! Implementation of the `help` subcommand. Prints basic usage info, or that
+//! of a single subcommand.
 
-use subcommand::Subcommand;
-use error::CliResult;
+use subcommand::{self, Subcommand};
+use error::{err, CliResult};
 use error::CommandResult;
 use term::Term;
 
-struct Help;
+struct Help {
+    command: Option<String>,
+}
 
 pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
     match name {
-        "help" | "--help" | "-h" | "-?" => Some(Box::new(Help)),
+        "help" | "--help" | "-h" | "-?" => Some(Box::new(Help { command: None })),
         _ => None
     }
 }
 
 impl Subcommand for Help {
-    fn parse_args(&mut self, _: &[String]) -> CliResult<()> {
-        Ok(())
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        match args.len() {
+            0 => Ok(()),
+            1 => {
+                self.command = Some(args[0].clone());
+                Ok(())
+            }
+            _ => Err(err(&format!("unexpected argument `{}`", args[1]))),
+        }
+    }
+    fn usage(&self) {
+        println!("Usage: rustbook help [<command>]");
+        println!("");
+        println!("Print the usage of rustbook, or of <command>.");
     }
-    fn usage(&self) {}
     fn execute(&mut self, _: &mut Term) -> CommandResult<()> {
-        usage();
+        match self.command {
+            Some(ref name) => {
+                match subcommand::parse_name(name) {
+                    Some(subcmd) => subcmd.usage(),
+                    None => return Err(err(&format!("unrecognized command '{}'", name))),
+                }
+            }
+            None => usage(),
+        }
         Ok(())
     }
 }
@@ -39,8 +61,13 @@ pub fn usage() {
     println!("Usage: rustbook <command> [<args>]");
     println!("");
     println!("The <command> must be one of:");
-    println!("  help    Print this message.");
-    println!("  build   Build the book in subdirectory _book");
-    println!("  serve   --NOT YET IMPLEMENTED--");
-    println!("  test    --NOT YET IMPLEMENTED--");
+    println!("  help       Print this message, or the usage of a command.");
+    println!("  init       Create a new book, or the missing chapters of an outline");
+    println!("  build      Build the book in subdirectory _book");
+    println!("  serve      Build the book and serve it locally, rebuilding on changes");
+    println!("  test       Test the code examples of the book");
+    println!("  linkcheck  Check the links of the book, and that no chapter is left out");
+    println!("  theme      Show the book's theme, or write the built-in one to edit");
+    println!("");
+    println!("Run `rustbook help <command>` for the options of a command.");
 }
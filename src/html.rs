@@ -0,0 +1,255 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rewriting the HTML that rustdoc renders, for the renderers that put
+//! chapters somewhere other than pages of their own.
+
+use std::char;
+
+/// Elements that have no content, and so need closing with `/>` in XHTML.
+const VOID_ELEMENTS: &'static [&'static str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+    "source", "track", "wbr",
+];
+
+/// The named character references of HTML that are most likely to turn up
+/// in a book. XML only knows `&amp;`, `&lt;`, `&gt;`, `&quot;` and `&apos;`,
+/// so these are turned into numeric ones.
+const ENTITIES: &'static [(&'static str, u32)] = &[
+    ("nbsp", 160), ("iexcl", 161), ("cent", 162), ("pound", 163), ("yen", 165),
+    ("sect", 167), ("copy", 169), ("laquo", 171), ("shy", 173), ("reg", 174), ("deg", 176),
+    ("plusmn", 177), ("micro", 181), ("para", 182), ("middot", 183), ("raquo", 187),
+    ("iquest", 191), ("times", 215), ("divide", 247), ("ensp", 8194), ("emsp", 8195),
+    ("thinsp", 8201), ("zwnj", 8204), ("zwj", 8205), ("ndash", 8211), ("mdash", 8212),
+    ("lsquo", 8216), ("rsquo", 8217), ("sbquo", 8218), ("ldquo", 8220), ("rdquo", 8221),
+    ("bdquo", 8222), ("dagger", 8224), ("Dagger", 8225), ("bull", 8226), ("hellip", 8230),
+    ("prime", 8242), ("Prime", 8243), ("euro", 8364), ("trade", 8482), ("larr", 8592),
+    ("uarr", 8593), ("rarr", 8594), ("darr", 8595), ("harr", 8596), ("lArr", 8656),
+    ("rArr", 8658), ("hArr", 8660), ("forall", 8704), ("exist", 8707), ("empty", 8709),
+    ("isin", 8712), ("notin", 8713), ("sum", 8721), ("minus", 8722), ("infin", 8734),
+    ("and", 8743), ("or", 8744), ("cap", 8745), ("cup", 8746), ("ne", 8800), ("le", 8804),
+    ("ge", 8805), ("sub", 8834), ("sup", 8835), ("lambda", 955),
+];
+
+/// An HTML tag, as parsed by `parse_tag`.
+struct Tag {
+    /// The element's name, in lowercase.
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    /// The attributes, with their names in lowercase, as HTML's do not
+    /// depend on case, and their values as written.
+    attributes: Vec<(String, Option<String>)>,
+}
+
+/// Parse the HTML tag at the start of `s`, returning it and its length.
+fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let closing = s.starts_with("</");
+    let mut i = if closing { 2 } else { 1 };
+    let name_len = s[i..].find(|c: char| !c.is_alphanumeric() && c != '-' && c != ':')
+                         .unwrap_or(s.len() - i);
+    if !s[i..].chars().next().map_or(false, |c| ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z')) {
+        return None;
+    }
+    let mut tag = Tag {
+        name: s[i..i + name_len].to_lowercase(),
+        closing: closing,
+        self_closing: false,
+        attributes: vec![],
+    };
+    i += name_len;
+
+    loop {
+        i += s[i..].len() - s[i..].trim_left().len();
+        let rest = &s[i..];
+        if rest.starts_with("/>") {
+            tag.self_closing = true;
+            return Some((tag, i + 2));
+        } else if rest.starts_with('>') {
+            return Some((tag, i + 1));
+        } else if rest.is_empty() || rest.starts_with('<') {
+            return None;
+        }
+
+        let name_len = rest.find(|c: char| {
+            c.is_whitespace() || c == '=' || c == '>' || c == '/' || c == '<'
+        }).unwrap_or(rest.len());
+        if name_len == 0 {
+            // a stray `/` or `=`
+            i += 1;
+            continue;
+        }
+        let name = rest[..name_len].to_lowercase();
+        i += name_len;
+        let after_name = s[i..].trim_left();
+        if !after_name.starts_with('=') {
+            tag.attributes.push((name, None));
+            continue;
+        }
+        i = s.len() - after_name.len() + 1;
+        i += s[i..].len() - s[i..].trim_left().len();
+        let rest = &s[i..];
+        let value = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let end = match rest[1..].find(quote) {
+                    Some(end) => end + 1,
+                    None => return None,
+                };
+                i += end + 1;
+                &rest[1..end]
+            }
+            _ => {
+                let end = rest.find(|c: char| c.is_whitespace() || c == '>')
+                              .unwrap_or(rest.len());
+                i += end;
+                &rest[..end]
+            }
+        };
+        tag.attributes.push((name, Some(value.to_string())));
+    }
+}
+
+/// Resolve the character reference at the start of `s`, if it is one, into
+/// one XML knows. Returns the reference to use and how much of `s` it
+/// replaces; an `&` that starts no known reference is escaped.
+fn char_reference(s: &str) -> (String, usize) {
+    let end = match s.find(';') {
+        Some(end) if end > 1 && end <= 32 => end,
+        _ => return ("&amp;".to_string(), 1),
+    };
+    let name = &s[1..end];
+    let code = if name.starts_with("#x") || name.starts_with("#X") {
+        u32::from_str_radix(&name[2..], 16).ok()
+    } else if name.starts_with('#') {
+        name[1..].parse().ok()
+    } else if ["amp", "lt", "gt", "quot", "apos"].contains(&name) {
+        return (s[..end + 1].to_string(), end + 1);
+    } else {
+        ENTITIES.iter().find(|&&(entity, _)| entity == name).map(|&(_, code)| code)
+    };
+    match code.and_then(char::from_u32) {
+        Some(c) if c >= ' ' || c == '\t' || c == '\n' || c == '\r' => {
+            (format!("&#{};", c as u32), end + 1)
+        }
+        _ => ("&amp;".to_string(), 1),
+    }
+}
+
+/// Turn a piece of HTML, as rustdoc renders it, into XHTML: void elements
+/// are closed, attribute values are quoted and character references that
+/// XML does not know are made numeric. Raw HTML in a chapter need not be
+/// well-formed, so elements left open are closed, and closing tags of
+/// elements that are not open are dropped.
+///
+/// `attribute` is given the name of every element along with the name and
+/// value of each of its attributes, and returns a value to use instead if
+/// need be. Names are in lowercase, values as written.
+pub fn to_xhtml<F>(html: &str, mut attribute: F) -> String
+    where F: FnMut(&str, &str, &str) -> Option<String>
+{
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<String> = vec![];
+    let mut rest = html;
+    while let Some(i) = rest.find(|c| c == '<' || c == '&' || c == '>') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('&') {
+            let (reference, len) = char_reference(rest);
+            out.push_str(&reference);
+            rest = &rest[len..];
+        } else if rest.starts_with('>') {
+            out.push_str("&gt;");
+            rest = &rest[1..];
+        } else if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some((tag, len)) = parse_tag(rest) {
+            rest = &rest[len..];
+            let void = VOID_ELEMENTS.contains(&&tag.name[..]);
+            if tag.closing {
+                if let Some(i) = open.iter().rposition(|name| *name == tag.name) {
+                    for name in open.drain(i..).rev() {
+                        out.push_str(&format!("</{}>", name));
+                    }
+                }
+                continue;
+            }
+            out.push('<');
+            out.push_str(&tag.name);
+            for &(ref name, ref value) in &tag.attributes {
+                let value = value.as_ref().unwrap_or(name);
+                let value = attribute(&tag.name, name, value).unwrap_or_else(|| value.clone());
+                out.push_str(&format!(" {}=\"{}\"", name, attribute_value(&value)));
+            }
+            if void || tag.self_closing {
+                out.push_str("/>");
+            } else {
+                out.push('>');
+                open.push(tag.name);
+            }
+        } else {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    for name in open.into_iter().rev() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+/// Quote the value of an attribute for XHTML.
+fn attribute_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find(|c| c == '&' || c == '<' || c == '"') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('&') {
+            let (reference, len) = char_reference(rest);
+            out.push_str(&reference);
+            rest = &rest[len..];
+        } else {
+            out.push_str(if rest.starts_with('<') { "&lt;" } else { "&quot;" });
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_xhtml;
+
+    #[test]
+    fn closes_and_quotes() {
+        assert_eq!(to_xhtml("<p>a<br>b &nbsp; <img src=x.png alt>", |_, _, _| None),
+                   "<p>a<br/>b &#160; <img src=\"x.png\" alt=\"alt\"/></p>");
+        assert_eq!(to_xhtml("a</div> > b", |_, _, _| None), "a &gt; b");
+    }
+
+    #[test]
+    fn attribute_names_in_any_case() {
+        let mut seen = vec![];
+        let xhtml = to_xhtml("<IMG SRC=\"a.png\"><A Href='b.html' ID=c>", |element, name, value| {
+            seen.push(format!("{} {} {}", element, name, value));
+            if name == "src" || name == "href" || name == "id" {
+                Some(format!("x-{}", value))
+            } else {
+                None
+            }
+        });
+        assert_eq!(seen, ["img src a.png", "a href b.html", "a id c"]);
+        assert_eq!(xhtml, "<img src=\"x-a.png\"/><a href=\"x-b.html\" id=\"x-c\"></a>");
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Implementation of the `init` subcommand, which sets up a new book or
+//! fills in the chapters of an outline.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+
+use subcommand::{parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
+use error::{err, CliResult, CommandResult, Error};
+use term::Term;
+use book;
+use config::{Config, CONFIG_FILE};
+use markdown;
+
+struct Init {
+    book: BookArgs,
+    toml: bool,
+}
+
+const INIT_OPTS: &'static [Opt] = &[
+    Opt { short: "", long: "toml", value: "", help: "Also write a book.toml with the defaults" },
+];
+
+const SUMMARY: &'static str = "\
+# Summary
+
+* [Chapter 1](chapter_1.md)
+";
+
+pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
+    if name == "init" {
+        Some(Box::new(Init { book: BookArgs::new(), toml: false }))
+    } else {
+        None
+    }
+}
+
+/// Write `contents` to `path`, unless there already is such a file.
+fn create(path: &Path, contents: &str, term: &mut Term) -> CliResult<()> {
+    if path.exists() { return Ok(()) }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    File::create(path).and_then(|mut f| f.write_all(contents.as_bytes()))
+                      .map_err(|e| Error::io(path, e))?;
+    term.info(&format!("Created {}", path.display()));
+    Ok(())
+}
+
+/// Make sure the build directory is ignored by git, if it is inside the
+/// book's directory.
+fn ignore_build_dir(root: &Path, config: &Config, term: &mut Term) -> CliResult<()> {
+    // unless book.toml says otherwise, the book will be built from its own
+    // directory
+    let build_dir = if config.build_dir == Config::default(root).build_dir {
+        root.join("_book")
+    } else {
+        config.build_dir.clone()
+    };
+    let relative = match build_dir.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(..) => return Ok(()),
+    };
+    let pattern = format!("/{}/", relative.display());
+    let path = root.join(".gitignore");
+
+    let mut existing = String::new();
+    if path.exists() {
+        File::open(&path).and_then(|mut f| f.read_to_string(&mut existing))
+                         .map_err(|e| Error::io(&path, e))?;
+    }
+    if existing.lines().any(|line| line.trim() == pattern) { return Ok(()) }
+
+    let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file, "")?;
+    }
+    writeln!(file, "{}", pattern)?;
+    term.info(&format!("Added {} to {}", pattern, path.display()));
+    Ok(())
+}
+
+impl Subcommand for Init {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, INIT_OPTS])?;
+        self.book.parse(&mut matches)?;
+        if let Some(arg) = matches.free.first() {
+            return Err(err(&format!("unexpected argument `{}`", arg)));
+        }
+        self.toml = matches.opt_present("toml");
+        Ok(())
+    }
+    fn usage(&self) {
+        print_usage("rustbook init [<src>] [options]",
+                    "Create a new book in <src> (default: the current directory), or the \
+                     chapters\nof its SUMMARY.md that do not exist yet.",
+                    &[BOOK_OPTS, INIT_OPTS]);
+    }
+    fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
+        self.book.configure(term);
+        let root = self.book.root();
+        fs::create_dir_all(&root)?;
+
+        if self.toml {
+            let title = root.file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or("My Book".to_string());
+            create(&root.join(CONFIG_FILE),
+                   &format!("title = \"{}\"\nauthors = []\n", title.replace("\"", "\\\"")),
+                   term)?;
+        }
+        let config = self.book.load_config()?;
+
+        create(&config.summary_path(), SUMMARY, term)?;
+        create(&config.src.join("README.md"), &format!("% {}\n", config.introduction), term)?;
+        ignore_build_dir(&root, &config, term)?;
+
+        // fill in the outline
+        let book = book::load(&config, term)?;
+        for (_, item) in book.iter() {
+            if item.is_draft() { continue }
+            create(&config.src.join(&item.path),
+                   &format!("% {}\n", markdown::inline_to_text(&item.title)),
+                   term)?;
+        }
+        Ok(())
+    }
+}
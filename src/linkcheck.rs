@@ -0,0 +1,382 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Implementation of the `linkcheck` subcommand, which checks that the links
+//! of a book lead somewhere.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rustc_back::tempdir::TempDir;
+
+use subcommand::{parse_jobs, parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
+use term::Term;
+use error::{err, CliResult, CommandResult, Error};
+use book::{self, BookItem};
+use build::{self, BuildOptions};
+use markdown;
+use pool;
+
+struct LinkCheck {
+    book: BookArgs,
+    /// Whether to fetch the links to other sites as well.
+    external: bool,
+    jobs: usize,
+}
+
+const LINKCHECK_OPTS: &'static [Opt] = &[
+    Opt { short: "", long: "external", value: "",
+          help: "Also check the links to other sites, by fetching them with curl" },
+    Opt { short: "j", long: "jobs", value: "N",
+          help: "Render N chapters, or fetch N links, at a time (default: 1)" },
+];
+
+pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
+    if name == "linkcheck" {
+        Some(Box::new(LinkCheck {
+            book: BookArgs::new(),
+            external: false,
+            jobs: 1,
+        }))
+    } else {
+        None
+    }
+}
+
+/// Something wrong with a file of the book.
+struct Problem {
+    path: PathBuf,
+    /// The line it is on, or 0 if it is about the whole file.
+    line: usize,
+    message: String,
+}
+
+/// What links within the book can lead to: its chapters, and the ids of the
+/// elements of their pages.
+struct Targets<'a> {
+    src: &'a Path,
+    /// The file name of the summary, for messages.
+    summary: &'a Path,
+    chapters: Vec<&'a BookItem>,
+    ids: HashMap<PathBuf, Vec<String>>,
+}
+
+impl<'a> Targets<'a> {
+    /// Check a link from the chapter `from` to another part of the book, or
+    /// to a part of `from` itself. `Err` says what is wrong with it.
+    fn check<'b>(&'b self, from: &'b BookItem, url: &str) -> Result<(), String> {
+        let end = url.find(|c| c == '#' || c == '?').unwrap_or(url.len());
+        let path = &url[..end];
+        let fragment = url.find('#').map_or("", |i| &url[i + 1..]);
+        if path.starts_with('/') {
+            // relative to wherever the book is served from, so anyone's guess
+            return Ok(());
+        }
+
+        let target = if path.is_empty() {
+            from
+        } else {
+            let dir = from.path.parent().unwrap_or(Path::new(""));
+            let path = match book::normalize(&dir.join(path)) {
+                Some(path) => path,
+                None => return Err("it leads out of the book".to_string()),
+            };
+            // the front page is a copy of the root README's
+            let front_page = path == Path::new("index.html");
+            let chapter = self.chapters.iter().find(|item| {
+                item.path == path || item.page() == path ||
+                (front_page && item.page() == Path::new("README.html"))
+            });
+            match chapter {
+                Some(chapter) => *chapter,
+                None if !self.src.join(&path).exists() => {
+                    return Err(format!("{} does not exist", path.display()));
+                }
+                None if book::is_markdown(&path) => {
+                    return Err(format!("{} is not in {}", path.display(),
+                                       self.summary.display()));
+                }
+                // some other file, like a picture
+                None => return Ok(()),
+            }
+        };
+
+        if fragment.is_empty() || self.ids[&target.path].iter().any(|id| id == fragment) {
+            Ok(())
+        } else {
+            Err(format!("{} has no heading with the id `{}`", target.path.display(), fragment))
+        }
+    }
+}
+
+/// The ids of the elements of an HTML page, which are what the fragment of
+/// a link to it can point at.
+fn element_ids(html: &str) -> Vec<String> {
+    let mut ids = vec![];
+    let mut rest = html;
+    while let Some(i) = rest.find(" id=") {
+        rest = &rest[i + 4..];
+        let quote = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        rest = &rest[1..];
+        if let Some(end) = rest.find(quote) {
+            ids.push(rest[..end].to_string());
+            rest = &rest[end..];
+        }
+    }
+    ids
+}
+
+/// Fetch `url` with curl, to see whether it leads anywhere. Some servers
+/// refuse HEAD requests, so a failed one is tried again as a GET. `Err`
+/// holds what curl had to say.
+fn fetch(url: &str) -> Result<(), String> {
+    let null = if cfg!(target_os = "windows") { "NUL" } else { "/dev/null" };
+    let mut message = String::new();
+    for &head in &[true, false] {
+        let mut command = Command::new("curl");
+        command.args(&["--silent", "--show-error", "--fail", "--location",
+                       "--max-time", "30", "--output", null]);
+        if head {
+            command.arg("--head");
+        }
+        match command.arg(url).output() {
+            Ok(ref output) if output.status.success() => return Ok(()),
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                message = stderr.trim().trim_left_matches("curl: ").to_string();
+            }
+            Err(e) => return Err(format!("could not run `curl`: {}", e)),
+        }
+    }
+    Err(message)
+}
+
+/// Collect the Markdown files under `dir`, in order, leaving out hidden
+/// directories and those in `skip`.
+fn markdown_files(dir: &Path, skip: &[&Path], files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+    for path in entries {
+        let hidden = path.file_name().map_or(false, |name| {
+            name.to_string_lossy().starts_with('.')
+        });
+        if path.is_dir() {
+            if !hidden && !skip.contains(&path.as_path()) {
+                markdown_files(&path, skip, files)?;
+            }
+        } else if book::is_markdown(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Subcommand for LinkCheck {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, LINKCHECK_OPTS])?;
+        self.book.parse(&mut matches)?;
+        if let Some(arg) = matches.free.first() {
+            return Err(err(&format!("unexpected argument `{}`", arg)));
+        }
+        self.external = matches.opt_present("external");
+        if let Some(jobs) = matches.opt_str("jobs") {
+            self.jobs = parse_jobs(&jobs)?;
+        }
+        Ok(())
+    }
+    fn usage(&self) {
+        print_usage("rustbook linkcheck [<src>] [options]",
+                    "Check that the links of the book in <src> (default: the current \
+                     directory) lead somewhere, and that every chapter is in its summary.",
+                    &[BOOK_OPTS, LINKCHECK_OPTS]);
+    }
+    fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
+        self.book.configure(term);
+        let mut config = self.book.load_config()?;
+
+        let book = book::load(&config, term)?;
+
+        // render the book out of the way, as the ids of the headings are
+        // whatever rustdoc makes of them
+        let tmp = TempDir::new("rustbook")?;
+        let build_dir = mem::replace(&mut config.build_dir, tmp.path().join("book"));
+        build::build(&config, &BuildOptions { jobs: self.jobs, ..BuildOptions::new() }, term)?;
+
+        let chapters: Vec<&BookItem> = book.iter()
+                                           .map(|(_, item)| item)
+                                           .filter(|item| !item.is_draft())
+                                           .collect();
+        let mut ids = HashMap::new();
+        for item in &chapters {
+            let path = config.build_dir.join(item.page());
+            let mut html = String::new();
+            File::open(&path).and_then(|mut f| f.read_to_string(&mut html))
+                             .map_err(|e| Error::io(&path, e))?;
+            ids.insert(item.path.clone(), element_ids(&html));
+        }
+        let targets = Targets {
+            src: &config.src,
+            summary: &config.summary,
+            chapters: chapters.clone(),
+            ids: ids,
+        };
+
+        let mut problems = vec![];
+        let mut links = 0;
+        // every link to another site, along with where it is linked from
+        let mut external: Vec<(String, Vec<(PathBuf, usize)>)> = vec![];
+        for item in &chapters {
+            let path = config.src.join(&item.path);
+            let mut source = String::new();
+            File::open(&path).and_then(|mut f| f.read_to_string(&mut source))
+                             .map_err(|e| Error::io(&path, e))?;
+            for (line, url) in markdown::links(&source) {
+                let url = markdown::unescape(&url);
+                links += 1;
+                if url.starts_with("http://") || url.starts_with("https://") {
+                    match external.iter().position(|&(ref known, _)| *known == url) {
+                        Some(i) => external[i].1.push((item.path.clone(), line)),
+                        None => external.push((url, vec![(item.path.clone(), line)])),
+                    }
+                } else if !markdown::has_scheme(&url) {
+                    if let Err(reason) = targets.check(item, &url) {
+                        problems.push(Problem {
+                            path: item.path.clone(),
+                            line: line,
+                            message: format!("broken link to `{}`: {}", url, reason),
+                        });
+                    }
+                }
+            }
+        }
+
+        if !self.external {
+            if !external.is_empty() {
+                term.note(&format!("{} links to other sites were not checked; pass \
+                                    `--external` to check them too", external.len()));
+            }
+        } else if !external.is_empty() {
+            if Command::new("curl").arg("--version").output().is_err() {
+                return Err(err("could not run `curl` to check the links to other sites; \
+                                install it, or leave out `--external`"));
+            }
+            term.verbose(&format!("Checking {} links to other sites", external.len()));
+            let urls = external.iter().map(|&(ref url, _)| url.clone()).collect();
+            let results = match pool::map(urls, self.jobs, |url: String| fetch(&url), |_| {}) {
+                Some(results) => results,
+                None => return Err(err("a thread checking links died")),
+            };
+            for ((url, sources), result) in external.into_iter().zip(results) {
+                if let Err(reason) = result {
+                    for (path, line) in sources {
+                        problems.push(Problem {
+                            path: path,
+                            line: line,
+                            message: format!("broken link to `{}`: {}", url, reason),
+                        });
+                    }
+                }
+            }
+        }
+        problems.sort_by_key(|problem| {
+            (chapters.iter().position(|item| item.path == problem.path), problem.line)
+        });
+
+        // chapters left out of the summary are not part of the book, which
+        // is easy to miss
+        let mut files = vec![];
+        markdown_files(&config.src, &[&build_dir, tmp.path()], &mut files)?;
+        for file in files {
+            let path = file.strip_prefix(&config.src).unwrap().to_path_buf();
+            if path == config.summary || chapters.iter().any(|item| item.path == path) {
+                continue;
+            }
+            problems.push(Problem {
+                path: path,
+                line: 0,
+                message: format!("not in {}, so not part of the book", config.summary.display()),
+            });
+        }
+
+        for problem in &problems {
+            if problem.line == 0 {
+                term.error(&format!("{}: {}", problem.path.display(), problem.message));
+            } else {
+                term.error(&format!("{}:{}: {}", problem.path.display(), problem.line,
+                                    problem.message));
+            }
+        }
+        term.info(&format!("Checked {} links in {} chapters", links, chapters.len()));
+        if !problems.is_empty() {
+            return Err(Error::Links(problems.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use rustc_back::tempdir::TempDir;
+
+    use book::{self, BookItem};
+    use config::Config;
+    use super::Targets;
+
+    #[test]
+    fn links_within_the_book() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let src = tmp.path();
+        fs::create_dir(src.join("sub")).unwrap();
+        for file in &["README.md", "a.md", "sub/b.markdown", "sub/c.markdown", "picture.png"] {
+            File::create(src.join(file)).unwrap();
+        }
+        let config = Config::default(src);
+        let summary = "* [A](a.md)\n    * [B](sub/b.markdown)\n";
+        let book = book::parse_summary(&mut summary.as_bytes(), &config).ok().unwrap();
+        let chapters: Vec<&BookItem> = book.iter().map(|(_, item)| item).collect();
+        let mut ids = HashMap::new();
+        for item in &chapters {
+            ids.insert(item.path.clone(), vec!["usage".to_string()]);
+        }
+        let targets = Targets {
+            src: src,
+            summary: &config.summary,
+            chapters: chapters.clone(),
+            ids: ids,
+        };
+        let b = chapters.iter().find(|item| item.path == Path::new("sub/b.markdown")).unwrap();
+        let check = |url: &str| targets.check(b, url).err().unwrap_or_default();
+
+        assert_eq!(check("../a.md#usage"), "");
+        assert_eq!(check("../a.html?x=1#usage"), "");
+        assert_eq!(check("#usage"), "");
+        assert_eq!(check("../picture.png"), "");
+        assert_eq!(check("/anywhere.html"), "");
+        assert_eq!(check("../README.md#usage"), "");
+        assert_eq!(check("../index.html#usage"), "");
+        assert_eq!(check("../a.md#nowhere"), "a.md has no heading with the id `nowhere`");
+        assert_eq!(check("c.markdown"), "sub/c.markdown is not in SUMMARY.md");
+        assert_eq!(check("d.md"), "sub/d.md does not exist");
+        assert_eq!(check("../../out.md"), "it leads out of the book");
+    }
+}
@@ -24,12 +24,25 @@ use term::Term;
 mod term;
 mod error;
 mod book;
+mod config;
+mod manifest;
+mod markdown;
+mod html;
+mod pool;
+mod search;
+mod template;
+mod zip;
 
 mod subcommand;
 mod help;
+mod init;
 mod build;
+mod epub;
+mod print;
 mod serve;
 mod test;
+mod linkcheck;
+mod theme;
 
 static EXIT_STATUS: AtomicIsize = ATOMIC_ISIZE_INIT;
 
@@ -37,31 +50,33 @@ pub fn main() {
     let mut term = Term::new();
     let cmd: Vec<_> = env::args().collect();
 
-    if cmd.len() <= 1 {
+    if env::var_os(test::RUSTDOC_ENV).is_some() {
+        process::exit(rustdoc::main_args(&cmd) as i32);
+    } else if cmd.len() <= 1 {
         help::usage()
     } else {
         match subcommand::parse_name(&cmd[1][..]) {
             Some(mut subcmd) => {
-                match subcmd.parse_args(&cmd[..cmd.len()-1]) {
+                match subcmd.parse_args(&cmd[2..]) {
                     Ok(_) => {
-                        match subcmd.execute(&mut term) {
-                            Ok(_) => (),
-                            Err(err) => {
-                                term.err(&format!("error: {}", err));
-                            }
+                        if let Err(err) = subcmd.execute(&mut term) {
+                            term.error(&error::describe(&err));
+                            EXIT_STATUS.store(err.exit_code() as isize, Ordering::SeqCst);
                         }
                     }
                     Err(err) => {
-                        println!("{}", err.description());
+                        term.error(&err.to_string());
                         println!("");
                         subcmd.usage();
+                        EXIT_STATUS.store(error::EXIT_USAGE as isize, Ordering::SeqCst);
                     }
                 }
             }
             None => {
-                println!("Unrecognized command '{}'.", cmd[1]);
+                term.error(&format!("unrecognized command '{}'", cmd[1]));
                 println!("");
                 help::usage();
+                EXIT_STATUS.store(error::EXIT_USAGE as isize, Ordering::SeqCst);
             }
         }
     }
@@ -0,0 +1,86 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The manifest of a built book, recording what each page was rendered from
+//! so that unchanged pages need not be rendered again.
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// The name of the manifest in the build directory.
+pub const MANIFEST_FILE: &'static str = ".rustbook-manifest";
+
+/// Maps every page of a built book, relative to the build directory, to a
+/// hash of everything it was rendered from.
+pub struct Manifest {
+    pages: BTreeMap<PathBuf, String>,
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest { pages: BTreeMap::new() }
+    }
+
+    /// Read the manifest in `path`. Lines that make no sense are ignored, as
+    /// the worst that can come of that is rendering a page again.
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let mut manifest = Manifest::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let mut parts = line.splitn(2, '\t');
+            if let (Some(hash), Some(page)) = (parts.next(), parts.next()) {
+                manifest.pages.insert(PathBuf::from(page), hash.to_string());
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for (page, hash) in &self.pages {
+            writeln!(out, "{}\t{}", hash, page.display())?;
+        }
+        out.flush()
+    }
+
+    pub fn get(&self, page: &Path) -> Option<&str> {
+        self.pages.get(page).map(|hash| &hash[..])
+    }
+
+    pub fn insert(&mut self, page: PathBuf, hash: String) {
+        self.pages.insert(page, hash);
+    }
+
+    pub fn remove(&mut self, page: &Path) -> Option<String> {
+        self.pages.remove(page)
+    }
+
+    pub fn pages(&self) -> Vec<PathBuf> {
+        self.pages.keys().cloned().collect()
+    }
+}
+
+/// Hash the inputs of a page. Hashes only need to stay the same from one
+/// build to the next, so the version of rustbook is part of them.
+pub fn hash(inputs: &[&[u8]]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    for input in inputs {
+        // keep ("ab", "c") apart from ("a", "bc")
+        hasher.write_usize(input.len());
+        hasher.write(input);
+    }
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,964 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Just enough of CommonMark to make sense of a book's structure. Rendering
+//! chapters is left to rustdoc.
+
+use std::cmp;
+use std::ops::Range;
+
+/// The marker at the start of a list item, e.g. `*`, `-`, `+` or `1.`.
+pub struct ListMarker {
+    /// Width of the indentation before the marker, in columns.
+    pub indent: usize,
+    /// Byte offset of the marker.
+    pub offset: usize,
+    /// Column at which the item's content starts. Following items indented
+    /// at least this far are nested inside this one.
+    pub content_column: usize,
+    /// Byte offset of the item's content.
+    pub content_offset: usize,
+}
+
+/// Measure the indentation of `line`, expanding tabs to multiples of four
+/// columns. Returns the width in columns and the byte offset of the first
+/// character that is neither a space nor a tab.
+pub fn indentation(line: &str) -> (usize, usize) {
+    let mut column = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            ' ' => column += 1,
+            '\t' => column += 4 - column % 4,
+            _ => return (column, i),
+        }
+    }
+    (column, line.len())
+}
+
+/// Recognize the list item marker a line starts with, if any.
+pub fn list_marker(line: &str) -> Option<ListMarker> {
+    let (indent, offset) = indentation(line);
+    let rest = &line[offset..];
+
+    let marker_len = match rest.chars().next() {
+        Some('*') | Some('-') | Some('+') => 1,
+        Some(c) if c.is_digit(10) => {
+            let digits = rest.chars().take_while(|c| c.is_digit(10)).count();
+            match rest[digits..].chars().next() {
+                Some('.') | Some(')') if digits <= 9 => digits + 1,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    // the marker has to be followed by whitespace, or end the line; tabs
+    // expand from the column the marker ends at
+    let marker_end = indent + marker_len;
+    let after = &rest[marker_len..];
+    let mut spaces = 0;
+    for c in after.chars() {
+        match c {
+            ' ' => spaces += 1,
+            '\t' => spaces += 4 - (marker_end + spaces) % 4,
+            _ => break,
+        }
+    }
+    if spaces == 0 && !after.is_empty() {
+        return None;
+    }
+
+    let content = after.trim_left_matches(|c| c == ' ' || c == '\t');
+    let content_column = if spaces > 4 || content.is_empty() {
+        // the content is an indented code block, or missing; either way it
+        // starts right after a single space
+        marker_end + 1
+    } else {
+        marker_end + spaces
+    };
+    Some(ListMarker {
+        indent: indent,
+        offset: offset,
+        content_column: content_column,
+        content_offset: line.len() - content.len(),
+    })
+}
+
+/// Whether `line` is a thematic break, such as `---` or `* * *`.
+pub fn is_thematic_break(line: &str) -> bool {
+    let (indent, offset) = indentation(line);
+    if indent > 3 { return false }
+    let rest = line[offset..].trim_right();
+    let rule = match rest.chars().next() {
+        Some(c) if c == '*' || c == '-' || c == '_' => c,
+        _ => return false,
+    };
+    rest.chars().all(|c| c == rule || c == ' ' || c == '\t') &&
+        rest.chars().filter(|&c| c == rule).count() >= 3
+}
+
+/// If `line` is an ATX heading, such as `# Part I`, return its text.
+pub fn atx_heading(line: &str) -> Option<&str> {
+    let (indent, offset) = indentation(line);
+    if indent > 3 { return None }
+    let rest = &line[offset..];
+    let level = rest.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 { return None }
+
+    let text = &rest[level..];
+    if !text.is_empty() && !text.starts_with(' ') && !text.starts_with('\t') {
+        return None;
+    }
+    // an optional closing sequence of `#`s, which must follow a space
+    let text = text.trim();
+    let unclosed = text.trim_right_matches('#');
+    if unclosed.is_empty() {
+        Some(unclosed)
+    } else if unclosed.ends_with(' ') || unclosed.ends_with('\t') {
+        Some(unclosed.trim_right())
+    } else {
+        Some(text)
+    }
+}
+
+/// A fence opening or closing a fenced code block.
+pub struct CodeFence<'a> {
+    /// The fence character, `` ` `` or `~`.
+    pub fence: char,
+    /// The number of fence characters.
+    pub len: usize,
+    /// Whatever follows the fence, such as the language of the code.
+    pub info: &'a str,
+}
+
+/// Recognize a code fence, such as ```` ```rust ```` or `~~~`.
+pub fn code_fence(line: &str) -> Option<CodeFence> {
+    let (indent, offset) = indentation(line);
+    if indent > 3 { return None }
+    let rest = &line[offset..];
+    let fence = match rest.chars().next() {
+        Some(c) if c == '`' || c == '~' => c,
+        _ => return None,
+    };
+    let len = rest.chars().take_while(|&c| c == fence).count();
+    let info = rest[len..].trim();
+    // backticks would be taken for a code span
+    if len < 3 || (fence == '`' && info.contains('`')) { return None }
+    Some(CodeFence { fence: fence, len: len, info: info })
+}
+
+impl<'a> CodeFence<'a> {
+    /// Whether `line` closes the code block this fence opened.
+    pub fn is_closed_by(&self, line: &str) -> bool {
+        match code_fence(line) {
+            Some(close) => close.fence == self.fence && close.len >= self.len &&
+                           close.info.is_empty(),
+            None => false,
+        }
+    }
+}
+
+/// A code block of a Markdown document, as found by `code_blocks`.
+pub struct CodeBlock<'a> {
+    /// The fence that opened the block, or `None` if it is indented.
+    pub fence: Option<CodeFence<'a>>,
+    /// The lines of the code, counted from 0. Those of a fenced code block
+    /// lie between the lines of its fences.
+    pub code: Range<usize>,
+}
+
+/// Find the code blocks of a Markdown document, in order. An indented line
+/// after a blank one starts a code block, unless it belongs to a list item;
+/// a fenced code block that is never closed runs to the end.
+pub fn code_blocks(doc: &str) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut fence: Option<(CodeFence, usize)> = None;
+    let mut indented: Option<Range<usize>> = None;
+    let mut in_list = false;
+    let mut after_blank = true;
+    let mut count = 0;
+
+    for (i, line) in doc.split('\n').enumerate() {
+        count += 1;
+        if let Some((open, start)) = fence.take() {
+            if open.is_closed_by(line) {
+                blocks.push(CodeBlock { fence: Some(open), code: start..i });
+            } else {
+                fence = Some((open, start));
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            after_blank = true;
+            continue;
+        }
+
+        let (indent, _) = indentation(line);
+        if indent >= 4 && (indented.is_some() || (after_blank && !in_list)) {
+            let start = indented.as_ref().map_or(i, |code| code.start);
+            indented = Some(start..i + 1);
+            after_blank = false;
+            continue;
+        }
+        after_blank = false;
+        if let Some(code) = indented.take() {
+            blocks.push(CodeBlock { fence: None, code: code });
+        }
+        if let Some(open) = code_fence(line) {
+            fence = Some((open, i + 1));
+            continue;
+        }
+        if list_marker(line).map_or(false, |marker| marker.indent < 4) {
+            in_list = true;
+        } else if indent == 0 {
+            in_list = false;
+        }
+    }
+    if let Some(code) = indented {
+        blocks.push(CodeBlock { fence: None, code: code });
+    }
+    if let Some((open, start)) = fence {
+        blocks.push(CodeBlock { fence: Some(open), code: start..count });
+    }
+    blocks
+}
+
+/// If a code span starts at `s[start]`, return the byte offset just past its
+/// closing backticks.
+pub fn code_span_end(s: &str, start: usize) -> Option<usize> {
+    let run = s[start..].chars().take_while(|&c| c == '`').count();
+    if run == 0 { return None }
+
+    let mut i = start + run;
+    while i < s.len() {
+        match s[i..].find('`') {
+            None => return None,
+            Some(found) => {
+                let open = i + found;
+                let len = s[open..].chars().take_while(|&c| c == '`').count();
+                if len == run { return Some(open + len) }
+                i = open + len;
+            }
+        }
+    }
+    None
+}
+
+/// Find the `]` closing the link text opened by the `[` at `s[open]`.
+/// Brackets nest, and neither backslash-escaped brackets nor brackets inside
+/// code spans count.
+pub fn link_text_end(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < s.len() {
+        let c = s[i..].chars().next().unwrap();
+        match c {
+            '\\' => {
+                i += 1;
+                if let Some(next) = s[i..].chars().next() { i += next.len_utf8() }
+                continue;
+            }
+            '`' => {
+                if let Some(end) = code_span_end(s, i) {
+                    i = end;
+                    continue;
+                }
+                i += s[i..].chars().take_while(|&c| c == '`').count();
+                continue;
+            }
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 { return Some(i) }
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+/// The destination of an inline link, as parsed by `link_destination`.
+pub struct LinkDestination {
+    /// The destination with backslash escapes resolved.
+    pub url: String,
+    /// Byte offset of the destination.
+    pub offset: usize,
+    /// Byte offset just past the destination as written, including any
+    /// `<...>` around it.
+    pub url_end: usize,
+    /// Byte offset just past the `)` closing the link.
+    pub end: usize,
+}
+
+/// The byte length of the spaces and tabs `s` starts with, along with at
+/// most one line break among them, as may come between the parts of a link.
+fn link_whitespace(s: &str) -> usize {
+    let (_, len) = indentation(s);
+    if s[len..].starts_with('\n') {
+        len + 1 + indentation(&s[len + 1..]).1
+    } else {
+        len
+    }
+}
+
+/// Parse the destination (and optional title) of an inline link whose `(` is
+/// at `s[open]`. Destinations may be wrapped in `<...>`; otherwise they end
+/// at whitespace or at an unbalanced `)`.
+pub fn link_destination(s: &str, open: usize) -> Option<LinkDestination> {
+    let offset = open + 1 + link_whitespace(&s[open + 1..]);
+    let rest = &s[offset..];
+
+    let (url, url_end) = if rest.starts_with('<') {
+        let close = match rest.find('>') {
+            Some(close) => close,
+            None => return None,
+        };
+        (unescape(&rest[1..close]), offset + close + 1)
+    } else {
+        let mut depth = 0;
+        let mut end = rest.len();
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => escaped = true,
+                '(' => depth += 1,
+                ')' if depth == 0 => { end = i; break }
+                ')' => depth -= 1,
+                c if c.is_whitespace() => { end = i; break }
+                _ => {}
+            }
+        }
+        (unescape(&rest[..end]), offset + end)
+    };
+
+    // an optional title, which we have no use for
+    let mut i = url_end;
+    i += link_whitespace(&s[i..]);
+    if let Some(quote) = s[i..].chars().next() {
+        let close_quote = match quote {
+            '"' => Some('"'),
+            '\'' => Some('\''),
+            '(' => Some(')'),
+            _ => None,
+        };
+        if let Some(close_quote) = close_quote {
+            match s[i + 1..].find(close_quote) {
+                Some(len) => i += len + 2,
+                None => return None,
+            }
+            i += link_whitespace(&s[i..]);
+        }
+    }
+
+    if s[i..].starts_with(')') {
+        Some(LinkDestination { url: url, offset: offset, url_end: url_end, end: i + 1 })
+    } else {
+        None
+    }
+}
+
+/// Whether a link destination starts with a scheme, such as `https:` or
+/// `mailto:`, and so leads out of the book.
+pub fn has_scheme(url: &str) -> bool {
+    url.find(':').map_or(false, |colon| {
+        colon > 0 && url[..colon].chars().all(|c| c.is_alphanumeric() || "+.-".contains(c))
+    })
+}
+
+/// If `line` starts a link reference definition, such as
+/// `[intro]: intro.md`, return the byte range of its destination as written,
+/// which may be on the next line.
+fn reference_destination(line: &str) -> Option<(usize, usize)> {
+    let (indent, offset) = indentation(line);
+    // `[^1]: ...` defines a footnote
+    if indent > 3 || !line[offset..].starts_with('[') || line[offset..].starts_with("[^") {
+        return None;
+    }
+    let close = match link_text_end(line, offset) {
+        Some(close) => close,
+        None => return None,
+    };
+    if !line[close + 1..].starts_with(':') { return None }
+    let start = close + 2 + link_whitespace(&line[close + 2..]);
+    let rest = &line[start..];
+    let len = if rest.starts_with('<') {
+        rest.find('>').map(|close| close + 1)
+    } else {
+        Some(rest.find(char::is_whitespace).unwrap_or(rest.len()))
+    };
+    match len {
+        Some(len) if len > 0 => Some((start, start + len)),
+        _ => None,
+    }
+}
+
+/// Rewrite the destinations of the links in `line`, which may be several
+/// lines of a paragraph, leaving code spans and images alone. `f` is also
+/// given the byte offset each link starts at. See `rewrite_links`.
+fn rewrite_inline_links<F>(line: &str, f: &mut F) -> String
+    where F: FnMut(usize, &str) -> Option<String>
+{
+    let mut out = String::with_capacity(line.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i..].chars().next().unwrap();
+        match c {
+            '\\' => {
+                i += 1;
+                if let Some(next) = line[i..].chars().next() { i += next.len_utf8() }
+                continue;
+            }
+            '`' => {
+                i = match code_span_end(line, i) {
+                    Some(end) => end,
+                    None => i + line[i..].chars().take_while(|&c| c == '`').count(),
+                };
+                continue;
+            }
+            '<' => {
+                // an autolink to a chapter becomes an ordinary link, as the
+                // text has to stay the same while the destination changes
+                let close = line[i..].find('>').map(|close| i + close);
+                if let Some(close) = close {
+                    let url = &line[i + 1..close];
+                    if !url.is_empty() && !url.contains(|c: char| c.is_whitespace() || c == '<') {
+                        if let Some(new_url) = f(i, url) {
+                            out.push_str(&line[copied..i]);
+                            out.push_str(&format!("[{}]({})", escape_markdown(url), new_url));
+                            i = close + 1;
+                            copied = i;
+                            continue;
+                        }
+                    }
+                }
+            }
+            '[' => {
+                let is_image = line[..i].ends_with('!');
+                let destination = link_text_end(line, i).and_then(|close| {
+                    if line[close + 1..].starts_with('(') {
+                        link_destination(line, close + 1)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(destination) = destination {
+                    let written = &line[destination.offset..destination.url_end];
+                    let angled = written.starts_with('<');
+                    let url = if angled { &written[1..written.len() - 1] } else { written };
+                    if !is_image {
+                        if let Some(new_url) = f(i, url) {
+                            out.push_str(&line[copied..destination.offset]);
+                            if angled {
+                                out.push_str(&format!("<{}>", new_url));
+                            } else {
+                                out.push_str(&new_url);
+                            }
+                            copied = destination.url_end;
+                        }
+                    }
+                    i = destination.end;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    out.push_str(&line[copied..]);
+    out
+}
+
+/// Rewrite the destinations of the links in a Markdown document: inline
+/// links, link reference definitions and autolinks, but not images. `f` is
+/// given every destination as written, and returns its replacement if it
+/// needs one. Code blocks, code spans and HTML blocks are left alone.
+///
+/// The rustdoc rustbook is built with renders Markdown with hoedown, whose
+/// parser it does not expose, so links are found by following CommonMark's
+/// rules for the few constructs that can hold them, here and below.
+pub fn rewrite_links<F>(doc: &str, mut f: F) -> String
+    where F: FnMut(&str) -> Option<String>
+{
+    map_links(doc, |_, url| f(url))
+}
+
+/// The destinations of the links in a Markdown document, as written, along
+/// with the lines they are on. See `rewrite_links`.
+pub fn links(doc: &str) -> Vec<(usize, String)> {
+    let mut links = vec![];
+    map_links(doc, |line, url| {
+        links.push((line, url.to_string()));
+        None
+    });
+    links
+}
+
+/// `rewrite_links`, with `f` also given the number of the line each link
+/// starts on.
+fn map_links<F>(doc: &str, mut f: F) -> String
+    where F: FnMut(usize, &str) -> Option<String>
+{
+    let lines: Vec<&str> = doc.split('\n').collect();
+    let mut is_code = vec![false; lines.len()];
+    for block in code_blocks(doc) {
+        // along with the fences of a fenced code block
+        let (start, end) = match block.fence {
+            Some(..) => (block.code.start - 1, cmp::min(block.code.end + 1, lines.len())),
+            None => (block.code.start, block.code.end),
+        };
+        for is_code in &mut is_code[start..end] {
+            *is_code = true;
+        }
+    }
+
+    // the text of a link may be wrapped onto the next line, so every run of
+    // lines between blank ones and code blocks is rewritten as a whole
+    let mut pieces = vec![];
+    let mut start = 0;
+    for i in 0..lines.len() + 1 {
+        if i < lines.len() && !is_code[i] && !lines[i].trim().is_empty() { continue }
+        if start < i && is_html_block(lines[start]) {
+            pieces.push(lines[start..i].join("\n"));
+        } else if start < i {
+            pieces.push(map_block_links(&lines[start..i].join("\n"), start + 1, &mut f));
+        }
+        if i < lines.len() {
+            pieces.push(lines[i].to_string());
+        }
+        start = i + 1;
+    }
+    pieces.join("\n")
+}
+
+/// Whether `line` starts a block of raw HTML, which is not Markdown, so has
+/// no links. As in hoedown, such blocks start with a comment or with one of
+/// the tags of block elements, and end at the next blank line.
+fn is_html_block(line: &str) -> bool {
+    const TAGS: &'static [&'static str] = &[
+        "blockquote", "del", "div", "dl", "fieldset", "figure", "form", "h1", "h2", "h3",
+        "h4", "h5", "h6", "iframe", "ins", "math", "noscript", "ol", "p", "pre", "script",
+        "style", "table", "ul",
+    ];
+    if line.starts_with("<!--") {
+        return true;
+    }
+    if !line.starts_with('<') {
+        return false;
+    }
+    let name_len = line[1..].find(|c: char| !c.is_digit(36)).unwrap_or(line.len() - 1);
+    let (name, after) = line[1..].split_at(name_len);
+    TAGS.contains(&&name.to_lowercase()[..]) &&
+    (after.is_empty() || after.starts_with(|c| c == '>' || c == '/' || c == ' ' || c == '\t'))
+}
+
+/// Rewrite the links of `block`, a run of lines of text such as a paragraph,
+/// whose first line is `first_line`. A link reference definition may start
+/// any of its lines.
+fn map_block_links<F>(block: &str, first_line: usize, f: &mut F) -> String
+    where F: FnMut(usize, &str) -> Option<String>
+{
+    let line_at = |offset: usize| first_line + block[..offset].matches('\n').count();
+    let mut out = String::with_capacity(block.len());
+    // where the text not rewritten yet starts, and the line being looked at
+    let mut text = 0;
+    let mut line = 0;
+    while line < block.len() {
+        let next_line = block[line..].find('\n').map_or(block.len(), |i| line + i + 1);
+        let (start, end) = match reference_destination(&block[line..]) {
+            Some((start, end)) => (line + start, line + end),
+            None => {
+                line = next_line;
+                continue;
+            }
+        };
+        out.push_str(&rewrite_inline_links(&block[text..line], &mut |offset, url| {
+            f(line_at(text + offset), url)
+        }));
+
+        // the definition ends with the line its destination is on
+        let written = &block[start..end];
+        let angled = written.starts_with('<');
+        let url = if angled { &written[1..written.len() - 1] } else { written };
+        let new_url = f(line_at(line), url).map(|new_url| {
+            if angled { format!("<{}>", new_url) } else { new_url }
+        });
+        out.push_str(&block[line..start]);
+        out.push_str(new_url.as_ref().map_or(written, |new_url| &new_url[..]));
+        line = block[end..].find('\n').map_or(block.len(), |i| end + i + 1);
+        out.push_str(&block[end..line]);
+        text = line;
+    }
+    out.push_str(&rewrite_inline_links(&block[text..], &mut |offset, url| {
+        f(line_at(text + offset), url)
+    }));
+    out
+}
+
+/// Backslash-escape the characters of `s` that could be taken for Markdown.
+fn escape_markdown(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\`*_[]<>!".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn is_escapable(c: char) -> bool {
+    "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".contains(c)
+}
+
+/// Resolve backslash escapes.
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars.peek() {
+                if is_escapable(next) {
+                    out.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Escape the characters that are special in HTML.
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Quote `s` as a JSON string, which is also a JavaScript string literal.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{2028}' => out.push_str("\\u2028"),
+            '\u{2029}' => out.push_str("\\u2029"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A piece of inline Markdown, as split up by `inline_pieces`.
+enum Inline<'a> {
+    /// Text, with backslash escapes resolved.
+    Text(&'a str),
+    /// The contents of a code span.
+    Code(&'a str),
+}
+
+/// Split inline Markdown into text and code spans, calling `f` for each
+/// piece in order.
+fn inline_pieces<F>(s: &str, mut f: F) where F: FnMut(Inline) {
+    let mut text = String::new();
+    let mut i = 0;
+    while i < s.len() {
+        let c = s[i..].chars().next().unwrap();
+        if c == '\\' {
+            if let Some(next) = s[i + 1..].chars().next() {
+                if is_escapable(next) {
+                    text.push(next);
+                    i += 1 + next.len_utf8();
+                    continue;
+                }
+            }
+        } else if c == '`' {
+            let run = s[i..].chars().take_while(|&c| c == '`').count();
+            if let Some(end) = code_span_end(s, i) {
+                f(Inline::Text(&text));
+                text.clear();
+                f(Inline::Code(s[i + run..end - run].trim()));
+                i = end;
+            } else {
+                text.push_str(&s[i..i + run]);
+                i += run;
+            }
+            continue;
+        }
+        text.push(c);
+        i += c.len_utf8();
+    }
+    f(Inline::Text(&text));
+}
+
+/// Render inline Markdown, such as a chapter title, to HTML. Only code spans
+/// and backslash escapes are understood; everything else is kept as text.
+pub fn inline_to_html(s: &str) -> String {
+    let mut out = String::new();
+    inline_pieces(s, |piece| {
+        match piece {
+            Inline::Text(text) => out.push_str(&escape_html(text)),
+            Inline::Code(code) => {
+                out.push_str("<code>");
+                out.push_str(&escape_html(code));
+                out.push_str("</code>");
+            }
+        }
+    });
+    out
+}
+
+/// Render inline Markdown, such as a chapter title, to plain text.
+pub fn inline_to_text(s: &str) -> String {
+    let mut out = String::new();
+    inline_pieces(s, |piece| {
+        match piece {
+            Inline::Text(text) | Inline::Code(text) => out.push_str(text),
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rewrite every destination ending in `.md`, unlike the build, which
+    /// knows better (see `build::chapter_link`), so as to show which
+    /// destinations `f` is given.
+    fn md_to_html(doc: &str) -> String {
+        rewrite_links(doc, |url| {
+            if url.ends_with(".md") {
+                Some(format!("{}.html", &url[..url.len() - 3]))
+            } else {
+                None
+            }
+        })
+    }
+
+    #[test]
+    fn indentation_expands_tabs() {
+        assert_eq!(indentation("  x"), (2, 2));
+        assert_eq!(indentation("\tx"), (4, 1));
+        assert_eq!(indentation("  \tx"), (4, 3));
+        assert_eq!(indentation("   "), (3, 3));
+    }
+
+    #[test]
+    fn list_markers() {
+        let marker = list_marker("  * item").unwrap();
+        assert_eq!((marker.indent, marker.offset), (2, 2));
+        assert_eq!((marker.content_column, marker.content_offset), (4, 4));
+        let marker = list_marker("10.  item").unwrap();
+        assert_eq!((marker.content_column, marker.content_offset), (5, 5));
+        let marker = list_marker("1)\titem").unwrap();
+        assert_eq!(marker.content_column, 4);
+        // the content is an indented code block, so starts one space in
+        let marker = list_marker("-      code").unwrap();
+        assert_eq!(marker.content_column, 2);
+        assert!(list_marker("-").is_some());
+        assert!(list_marker("*emphasis*").is_none());
+        assert!(list_marker("1.5 apples").is_none());
+        assert!(list_marker("1234567890. too long").is_none());
+    }
+
+    #[test]
+    fn thematic_breaks() {
+        assert!(is_thematic_break("---"));
+        assert!(is_thematic_break(" * * *"));
+        assert!(is_thematic_break("_____  "));
+        assert!(!is_thematic_break("--"));
+        assert!(!is_thematic_break("-*-"));
+        assert!(!is_thematic_break("    ---"));
+    }
+
+    #[test]
+    fn atx_headings() {
+        assert_eq!(atx_heading("# Part I"), Some("Part I"));
+        assert_eq!(atx_heading("### Part II ###"), Some("Part II"));
+        assert_eq!(atx_heading("## C#"), Some("C#"));
+        assert_eq!(atx_heading("#"), Some(""));
+        assert_eq!(atx_heading("#hashtag"), None);
+        assert_eq!(atx_heading("####### seven"), None);
+        assert_eq!(atx_heading("    # code"), None);
+    }
+
+    #[test]
+    fn code_fences() {
+        let fence = code_fence("```rust,ignore").unwrap();
+        assert_eq!((fence.fence, fence.len, fence.info), ('`', 3, "rust,ignore"));
+        assert!(fence.is_closed_by("````"));
+        assert!(!fence.is_closed_by("~~~"));
+        assert!(!fence.is_closed_by("``"));
+        assert!(!fence.is_closed_by("``` rust"));
+        assert!(code_fence("~~~~").is_some());
+        assert!(code_fence("``` a`b").is_none());
+        assert!(code_fence("    ```").is_none());
+    }
+
+    #[test]
+    fn finds_code_blocks() {
+        let doc = "text\n\n    code\n\n    more\n\n```rust\nfenced\n```\n\
+                   * item\n\n    not code\n\ntext\n    not code\n~~~\nunclosed";
+        let blocks: Vec<_> = code_blocks(doc).into_iter().map(|block| {
+            (block.fence.map(|fence| fence.info), block.code)
+        }).collect();
+        assert_eq!(blocks, [(None, 2..5), (Some("rust"), 7..8), (Some(""), 16..17)]);
+    }
+
+    #[test]
+    fn link_text() {
+        assert_eq!(link_text_end("[a [b] c]", 0), Some(8));
+        assert_eq!(link_text_end("[a \\] b]", 0), Some(7));
+        assert_eq!(link_text_end("[a `]` b]", 0), Some(8));
+        assert_eq!(link_text_end("[a `` ` `` b]", 0), Some(12));
+        assert_eq!(link_text_end("[a", 0), None);
+    }
+
+    #[test]
+    fn link_destinations() {
+        let dest = link_destination("(a.md)", 0).unwrap();
+        assert_eq!((&dest.url[..], dest.offset, dest.url_end, dest.end), ("a.md", 1, 5, 6));
+        let dest = link_destination("(<a b.md>)", 0).unwrap();
+        assert_eq!((&dest.url[..], dest.url_end), ("a b.md", 9));
+        assert_eq!(link_destination("(f(x).md)", 0).unwrap().url, "f(x).md");
+        assert_eq!(link_destination("(a\\).md)", 0).unwrap().url, "a).md");
+        assert_eq!(link_destination("( a.md \"title\" )", 0).unwrap().end, 16);
+        assert_eq!(link_destination("(a.md 'title')", 0).unwrap().url, "a.md");
+        assert!(link_destination("(a.md", 0).is_none());
+        assert!(link_destination("(a.md \"title)", 0).is_none());
+    }
+
+    #[test]
+    fn schemes() {
+        assert!(has_scheme("https://www.rust-lang.org/"));
+        assert!(has_scheme("mailto:someone@example.com"));
+        assert!(!has_scheme("a.md"));
+        assert!(!has_scheme("a.md#b:c"));
+        assert!(!has_scheme(":a"));
+    }
+
+    #[test]
+    fn rewrites_inline_links() {
+        assert_eq!(md_to_html("See [the intro](intro.md)."), "See [the intro](intro.html).");
+        assert_eq!(md_to_html("[a](a.md \"A\") and [b](<b c.md>)"),
+                   "[a](a.html \"A\") and [b](<b c.html>)");
+        assert_eq!(md_to_html("[*a* [b]](a.md)"), "[*a* [b]](a.html)");
+    }
+
+    #[test]
+    fn gives_destinations_as_written() {
+        let mut urls = vec![];
+        rewrite_links("[a](a.md#b \"T\") [c](<d e.md?f>) [g]\n\n[g]: https://h/i.md", |url| {
+            urls.push(url.to_string());
+            None
+        });
+        assert_eq!(urls, ["a.md#b", "d e.md?f", "https://h/i.md"]);
+    }
+
+    #[test]
+    fn leaves_html_blocks_alone() {
+        assert_eq!(md_to_html("<div class=\"x\">\n[a](a.md)\n</div>\n\n[b](b.md)"),
+                   "<div class=\"x\">\n[a](a.md)\n</div>\n\n[b](b.html)");
+        assert_eq!(md_to_html("<!-- [a](a.md) -->\n\n<span>[b](b.md)</span>"),
+                   "<!-- [a](a.md) -->\n\n<span>[b](b.html)</span>");
+        assert_eq!(md_to_html("<divider>[a](a.md)"), "<divider>[a](a.html)");
+    }
+
+    #[test]
+    fn rewrites_references_and_autolinks() {
+        assert_eq!(md_to_html("[intro]: intro.md"), "[intro]: intro.html");
+        assert_eq!(md_to_html("  [intro]: <intro.md> \"Intro\""),
+                   "  [intro]: <intro.html> \"Intro\"");
+        assert_eq!(md_to_html("[^1]: note.md"), "[^1]: note.md");
+        assert_eq!(md_to_html("See <intro.md>."), "See [intro.md](intro.html).");
+    }
+
+    #[test]
+    fn leaves_code_and_images_alone() {
+        assert_eq!(md_to_html("![a](a.md)"), "![a](a.md)");
+        assert_eq!(md_to_html("`[a](a.md)` [b](b.md)"), "`[a](a.md)` [b](b.html)");
+        assert_eq!(md_to_html("\\[a](a.md)"), "\\[a](a.md)");
+        assert_eq!(md_to_html("```\n[a](a.md)\n```\n[b](b.md)"),
+                   "```\n[a](a.md)\n```\n[b](b.html)");
+        assert_eq!(md_to_html("text\n\n    [a](a.md)\n\n[b](b.md)"),
+                   "text\n\n    [a](a.md)\n\n[b](b.html)");
+        // but not indented list items
+        assert_eq!(md_to_html("* item\n\n    [a](a.md)"), "* item\n\n    [a](a.html)");
+    }
+
+    #[test]
+    fn rewrites_links_across_lines() {
+        assert_eq!(md_to_html("See [the chapter on\nownership](ownership.md)."),
+                   "See [the chapter on\nownership](ownership.html).");
+        assert_eq!(md_to_html("[a](\na.md\n\"A\")"), "[a](\na.html\n\"A\")");
+        assert_eq!(md_to_html("[intro]:\n    intro.md \"Intro\"\n[b]: b.md"),
+                   "[intro]:\n    intro.html \"Intro\"\n[b]: b.html");
+        assert_eq!(md_to_html("Text [a\nb](a.md)\n[c]: c.md\nmore [d\n\n](d.md)"),
+                   "Text [a\nb](a.html)\n[c]: c.html\nmore [d\n\n](d.md)");
+        // blank lines and code blocks end paragraphs
+        assert_eq!(md_to_html("[a\n\nb](a.md)"), "[a\n\nb](a.md)");
+        assert_eq!(md_to_html("[a\n```\n](a.md)\n```"), "[a\n```\n](a.md)\n```");
+    }
+
+    #[test]
+    fn finds_wrapped_links_with_lines() {
+        let links = links("[a\nb](a.md) [c](\nc.md)\n\n[d]:\nd.md\n[e]: e.md");
+        assert_eq!(links, [(1, "a.md".to_string()), (2, "c.md".to_string()),
+                           (5, "d.md".to_string()), (7, "e.md".to_string())]);
+    }
+
+    #[test]
+    fn finds_links_with_lines() {
+        let links = links("# Title\n\n[a](a.md) and [b](b.md)\n\n```\n[c](c.md)\n```\n[d]: d.md");
+        assert_eq!(links, [(3, "a.md".to_string()), (3, "b.md".to_string()),
+                           (8, "d.md".to_string())]);
+    }
+
+    #[test]
+    fn inline_markdown() {
+        assert_eq!(inline_to_html("A <b> & `Vec<T>`"),
+                   "A &lt;b&gt; &amp; <code>Vec&lt;T&gt;</code>");
+        assert_eq!(unescape("a\\*b\\c"), "a*b\\c");
+    }
+
+    #[test]
+    fn inline_text() {
+        assert_eq!(inline_to_text("\\[a\\] `b`"), "[a] b");
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("a\"b\n\u{2028}"), "\"a\\\"b\\n\\u2028\"");
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A pool of threads to work through the chapters of a book with.
+
+use std::cmp;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// rustdoc runs on threads with stacks this large, and so do we.
+const STACK_SIZE: usize = 32_000_000;
+
+/// Call `f` on every job, on up to `threads` threads at a time, and return
+/// the results in the order of the jobs. `done` is called with each result
+/// in that same order, as soon as it and all the results before it are in.
+/// Returns `None` if a thread panicked or could not be started, once the
+/// threads that did start are done.
+pub fn map<T, R, F, D>(jobs: Vec<T>, threads: usize, f: F, mut done: D) -> Option<Vec<R>>
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> R + Send + Sync + 'static,
+          D: FnMut(&R)
+{
+    let len = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    let mut handles = vec![];
+    for _ in 0..cmp::min(cmp::max(threads, 1), len) {
+        let jobs = queue.clone();
+        let f = f.clone();
+        let tx = tx.clone();
+        let spawned = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+            loop {
+                // the lock must not be held while working
+                let next = jobs.lock().unwrap().next();
+                let (i, job) = match next {
+                    Some(next) => next,
+                    None => return,
+                };
+                if tx.send((i, f(job))).is_err() { return }
+            }
+        });
+        match spawned {
+            Ok(handle) => handles.push(handle),
+            Err(..) => {
+                // leave the threads only the jobs they are working on
+                if let Ok(mut queue) = queue.lock() {
+                    queue.by_ref().count();
+                }
+                for handle in handles {
+                    let _ = handle.join();
+                }
+                return None;
+            }
+        }
+    }
+    drop(tx);
+
+    let mut results: Vec<Option<R>> = (0..len).map(|_| None).collect();
+    let mut reported = 0;
+    for (i, result) in rx {
+        results[i] = Some(result);
+        while reported < len {
+            match results[reported] {
+                Some(ref result) => done(result),
+                None => break,
+            }
+            reported += 1;
+        }
+    }
+    results.into_iter().collect()
+}
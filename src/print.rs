@@ -0,0 +1,265 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The print renderer, which puts every chapter on a single page that can be
+//! printed, or saved as a PDF, as a whole.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use rustc_back::tempdir::TempDir;
+
+use book;
+use build::{self, BuildOptions};
+use config::Config;
+use error::{CommandResult, Error};
+use html;
+use markdown;
+use term::Term;
+use theme::Theme;
+
+/// The name of the page in the build directory.
+pub const PRINT_FILE: &'static str = "print.html";
+
+/// Styles for the page, on top of the book's own. Headings stay with what
+/// follows them.
+const PRINT_CSS: &'static str = r#"
+body {
+    max-width: 750px;
+    margin: 0 auto;
+    padding: 0 15px;
+}
+
+#title-page {
+    text-align: center;
+    padding-top: 30vh;
+}
+
+#print-toc ol {
+    list-style: none;
+    padding-left: 20px;
+}
+
+#print-toc > ol {
+    padding-left: 0;
+}
+
+h1, h2, h3, h4, h5, h6 {
+    page-break-after: avoid;
+    break-after: avoid;
+}
+
+pre, blockquote, table, img {
+    page-break-inside: avoid;
+    break-inside: avoid;
+}
+
+p {
+    orphans: 3;
+    widows: 3;
+}
+
+@page {
+    margin: 2cm;
+}
+
+@media only print {
+    a {
+        color: inherit;
+        text-decoration: none;
+    }
+}
+"#;
+
+/// Styles starting every chapter on a new sheet of paper, unless
+/// `[output.print]` says otherwise.
+const PAGE_BREAK_CSS: &'static str = r#"
+#print-toc,
+.print-chapter {
+    page-break-before: always;
+    break-before: page;
+}
+"#;
+
+/// Turn the path of a page into the id of its chapter on the print page.
+fn slug(page: &Path) -> String {
+    let page = page.with_extension("");
+    page.to_string_lossy().chars().map(|c| {
+        if c.is_alphanumeric() { c.to_lowercase().next().unwrap_or(c) } else { '-' }
+    }).collect()
+}
+
+/// Where a link from the chapter with the id `own`, whose page was in `dir`,
+/// leads on the print page: links to chapters lead to where they start, or
+/// to their headings. `None` if the link can stay as it is.
+fn print_link(url: &str, dir: &Path, chapters: &HashMap<PathBuf, String>, own: &str)
+              -> Option<String> {
+    if url.starts_with('#') {
+        return Some(anchor(own, &url[1..]));
+    }
+    if markdown::has_scheme(url) || url.starts_with('/') {
+        return None;
+    }
+    let end = url.find(|c| c == '#' || c == '?').unwrap_or(url.len());
+    let fragment = url.find('#').map_or("", |i| &url[i + 1..]);
+    match book::normalize(&dir.join(&url[..end])).and_then(|page| chapters.get(&page)) {
+        Some(id) => Some(anchor(id, fragment)),
+        None => from_root(url, dir),
+    }
+}
+
+/// Make a relative URL from a page in `dir` relative to the root of the book
+/// instead, where the print page is. `None` if it can stay as it is.
+fn from_root(url: &str, dir: &Path) -> Option<String> {
+    if markdown::has_scheme(url) || url.starts_with('/') || dir.as_os_str().is_empty() {
+        return None;
+    }
+    let dir = dir.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>();
+    Some(format!("{}/{}", dir.join("/"), url))
+}
+
+/// The anchor of the element `id` of the chapter `chapter`, or of the
+/// chapter itself.
+fn anchor(chapter: &str, id: &str) -> String {
+    if id.is_empty() { format!("#{}", chapter) } else { format!("#{}:{}", chapter, id) }
+}
+
+/// Build the book described by `config` as a single page in its build
+/// directory, and return the page's path. Of `options`, only `strict` and
+/// `jobs` apply.
+pub fn build(config: &Config, options: &BuildOptions, term: &mut Term) -> CommandResult<PathBuf> {
+    // render the pages as for the web, but without "Run" buttons, which
+    // are no use on paper
+    let tmp = TempDir::new("rustbook")?;
+    let mut html_config = config.clone();
+    html_config.build_dir = tmp.path().join("html");
+    html_config.html.playground_url = String::new();
+    let html_options = BuildOptions {
+        strict: options.strict,
+        jobs: options.jobs,
+        ..BuildOptions::new()
+    };
+    build::build(&html_config, &html_options, term)?;
+
+    let book = book::load(config, term)?;
+    let title = config.book_title();
+
+    // every chapter gets an id, which the ids within it are prefixed with
+    let mut chapters = HashMap::new();
+    let mut ids: Vec<String> = vec![];
+    for (_, item) in book.iter().filter(|&(_, item)| !item.is_draft()) {
+        let base = slug(&item.page());
+        let mut id = base.clone();
+        let mut n = 1;
+        while ids.contains(&id) {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        ids.push(id.clone());
+        chapters.insert(item.page(), id);
+    }
+
+    let mut page = String::new();
+    page.push_str(&format!("<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n\
+                            <meta charset=\"utf-8\">\n<title>{}</title>\n\
+                            <meta name=\"generator\" content=\"rustbook\">\n",
+                           markdown::escape_html(&config.language),
+                           markdown::escape_html(&title)));
+    if !config.authors.is_empty() {
+        page.push_str(&format!("<meta name=\"author\" content=\"{}\">\n",
+                               markdown::escape_html(&config.authors.join(", "))));
+    }
+    let page_break = if config.print.page_break { PAGE_BREAK_CSS } else { "" };
+    page.push_str(&format!("<style>\n{}\n{}{}</style>\n</head>\n<body class=\"rustdoc\">\n",
+                           build::stylesheet(&Theme::load(config)?), PRINT_CSS, page_break));
+
+    page.push_str(&format!("<div id=\"title-page\">\n<h1>{}</h1>\n",
+                           markdown::escape_html(&title)));
+    if !config.authors.is_empty() {
+        page.push_str(&format!("<p class=\"authors\">{}</p>\n",
+                               markdown::escape_html(&config.authors.join(", "))));
+    }
+    if let Some(ref description) = config.description {
+        page.push_str(&format!("<p class=\"description\">{}</p>\n",
+                               markdown::escape_html(description)));
+    }
+    page.push_str("</div>\n");
+
+    let mut toc = String::new();
+    build::toc_items(&book.chapters, "", &|item| anchor(&chapters[&item.page()], ""), &mut toc);
+    page.push_str(&format!("<nav id=\"print-toc\">\n<h1>Contents</h1>\n\
+                            <ol class=\"chapter\">\n{}</ol>\n</nav>\n", toc));
+
+    let mut images: Vec<PathBuf> = vec![];
+    for (section, item) in book.iter().filter(|&(_, item)| !item.is_draft()) {
+        term.verbose(&format!("Adding {}", item.path.display()));
+        let page_path = html_config.build_dir.join(item.page());
+        let mut html = String::new();
+        File::open(&page_path).and_then(|mut f| f.read_to_string(&mut html))
+                              .map_err(|e| Error::io(&page_path, e))?;
+        let id = &chapters[&item.page()];
+        let dir = item.page().parent().map_or(PathBuf::new(), |dir| dir.to_path_buf());
+        let mut sources = vec![];
+        let content = html::to_xhtml(build::page_content(&html), |element, name, value| {
+            if element == "img" && name == "src" {
+                sources.push(value.to_string());
+            }
+            match name {
+                "id" => Some(format!("{}:{}", id, value)),
+                "href" => print_link(value, &dir, &chapters, id),
+                "src" => from_root(value, &dir),
+                _ => None,
+            }
+        });
+
+        // the pictures on the page are copied next to print.html, if they
+        // are part of the book
+        for source in sources {
+            let end = source.find(|c| c == '#' || c == '?').unwrap_or(source.len());
+            if markdown::has_scheme(&source) || source.starts_with('/') || end == 0 {
+                continue;
+            }
+            match book::normalize(&dir.join(&source[..end])) {
+                Some(ref file) if config.src.join(file).is_file() => {
+                    if !images.contains(file) {
+                        images.push(file.clone());
+                    }
+                }
+                _ => term.warn(&format!("{} shows `{}`, which is not a file of the \
+                                         book, so it is not copied", item.path.display(), source)),
+            }
+        }
+
+        // number the chapter's title as in the table of contents
+        let content = if section.is_empty() {
+            content
+        } else {
+            content.replacen("<h1 class=\"title\">",
+                             &format!("<h1 class=\"title\">{} ", section), 1)
+        };
+        page.push_str(&format!("<section class=\"print-chapter\" id=\"{}\">\n{}\n</section>\n",
+                               id, content.trim()));
+    }
+    page.push_str("</body>\n</html>\n");
+
+    for image in &images {
+        let (from, to) = (config.src.join(image), config.build_dir.join(image));
+        if from != to {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+            }
+            fs::copy(&from, &to).map_err(|e| Error::io(&to, e))?;
+        }
+    }
+    let path = config.build_dir.join(PRINT_FILE);
+    build::write_file(&path, page.as_bytes())?;
+    Ok(path)
+}
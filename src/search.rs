@@ -0,0 +1,230 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The search index of a built book, which `rustbook.js` searches in the
+//! reader's browser. It is made from the rendered pages, so that it links to
+//! headings by the ids rustdoc gave them.
+
+use std::char;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io;
+use std::path::Path;
+
+use book::Book;
+use build;
+use error::{CommandResult, Error};
+use markdown;
+
+/// The name of the search index in the build directory. It is a script
+/// rather than JSON, so that it can be loaded from `file:` URLs too.
+pub const INDEX_FILE: &'static str = "searchindex.js";
+
+/// Tags that end a line of text, or a cell of a table, and so a word.
+const BREAKING_TAGS: &'static [&'static str] = &[
+    "br", "p", "li", "dd", "dt", "td", "th", "tr", "pre", "div", "blockquote",
+    "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// The part of a page from one heading up to the next, which is what a
+/// search leads to.
+struct Section {
+    /// The id of the heading, or `""` for the start of the page.
+    anchor: String,
+    /// The text of the heading, or `""` for the start of the page.
+    heading: String,
+    text: String,
+}
+
+/// Index every page of `book` that has been rendered into `dir`, and write
+/// the index there. Each entry of the index is a section of a page. Pages
+/// left out of every build so far, with `--only`, are left out of the index.
+pub fn write_index(book: &Book, dir: &Path) -> CommandResult<()> {
+    let mut out = vec![];
+    write!(out, "window.searchIndex = [")?;
+    let mut first = true;
+    for (section, item) in book.iter() {
+        if item.is_draft() { continue }
+        let page = item.page();
+        let path = dir.join(&page);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+        let mut html = String::new();
+        file.read_to_string(&mut html).map_err(|e| Error::io(&path, e))?;
+
+        let page = page.display().to_string().replace('\\', "/");
+        let title = markdown::inline_to_text(&item.title);
+        for part in page_sections(&html) {
+            if part.heading.is_empty() && part.text.is_empty() { continue }
+            write!(out, "{}\n{{\"page\":{},\"section\":{},\"chapter\":{},\"anchor\":{},\
+                         \"heading\":{},\"text\":{}}}",
+                   if first { "" } else { "," },
+                   markdown::json_string(&page),
+                   markdown::json_string(&section),
+                   markdown::json_string(&title),
+                   markdown::json_string(&part.anchor),
+                   markdown::json_string(&part.heading),
+                   markdown::json_string(&part.text))?;
+            first = false;
+        }
+    }
+    writeln!(out, "\n];")?;
+    let path = dir.join(INDEX_FILE);
+    File::create(&path).and_then(|mut f| f.write_all(&out)).map_err(|e| Error::io(&path, e))
+}
+
+/// Split the content of a rendered page into sections, at every heading
+/// with an id. The page's title has none, and is left out, as the chapter's
+/// title is indexed anyway.
+fn page_sections(html: &str) -> Vec<Section> {
+    let content = build::page_content(html);
+    let content = match content.find("</h1>") {
+        Some(i) if content[..i].contains("class=\"title\"") => &content[i + 5..],
+        _ => content,
+    };
+
+    let mut sections = vec![];
+    let mut anchor = String::new();
+    let mut heading = String::new();
+    let mut rest = content;
+    loop {
+        let next = next_heading(rest);
+        let end = next.as_ref().map_or(rest.len(), |&(start, _, _, _)| start);
+        sections.push(Section {
+            anchor: anchor,
+            heading: heading,
+            text: html_to_text(&rest[..end]),
+        });
+        match next {
+            Some((_, end, id, text)) => {
+                anchor = id;
+                heading = text;
+                rest = &rest[end..];
+            }
+            None => return sections,
+        }
+    }
+}
+
+/// Find the next heading with an id in `html`, and return where it starts
+/// and ends, its id and its text.
+fn next_heading(html: &str) -> Option<(usize, usize, String, String)> {
+    let mut from = 0;
+    while let Some(i) = html[from..].find("<h") {
+        let start = from + i;
+        from = start + 2;
+        let level = match html[from..].chars().next() {
+            Some(level) if '1' <= level && level <= '6' => level,
+            _ => continue,
+        };
+        let tag_end = match html[start..].find('>') {
+            Some(i) => start + i + 1,
+            None => return None,
+        };
+        let id = match attribute(&html[start..tag_end], "id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let close = format!("</h{}>", level);
+        let end = match html[tag_end..].find(&close[..]) {
+            Some(i) => tag_end + i,
+            None => return None,
+        };
+        let text = html_to_text(&html[tag_end..end]);
+        return Some((start, end + close.len(), decode_entities(&id), text));
+    }
+    None
+}
+
+/// The value of the attribute `name` of the HTML tag `tag`, quoted either
+/// way.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let prefix = format!(" {}=", name);
+    let rest = match tag.find(&prefix[..]) {
+        Some(i) => &tag[i + prefix.len()..],
+        None => return None,
+    };
+    let quote = match rest.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => quote,
+        _ => return None,
+    };
+    let rest = &rest[1..];
+    rest.find(quote).map(|end| rest[..end].to_string())
+}
+
+/// The text of a piece of HTML, with whitespace collapsed. The links to the
+/// playground rustdoc adds to code blocks are left out.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..open]));
+        let close = match rest[open..].find('>') {
+            Some(i) => open + i + 1,
+            None => { rest = ""; break }
+        };
+        let tag = &rest[open..close];
+        rest = &rest[close..];
+        if tag.starts_with("<a ") && tag.contains("test-arrow") {
+            rest = rest.find("</a>").map_or("", |i| &rest[i + 4..]);
+            continue;
+        }
+        let name = tag.trim_left_matches(|c| c == '<' || c == '/')
+                      .split(|c: char| c == '>' || c == '/' || c.is_whitespace())
+                      .next()
+                      .unwrap_or("")
+                      .to_lowercase();
+        if BREAKING_TAGS.contains(&&name[..]) {
+            text.push(' ');
+        }
+    }
+    text.push_str(&decode_entities(rest));
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Resolve the character references in HTML text, such as `&amp;` and
+/// `&#39;`. Unknown ones are kept as they are.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => { out.push('&'); rest = &rest[1..]; continue }
+        };
+        let name = &rest[1..end];
+        let c = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ if name.starts_with("#x") || name.starts_with("#X") => {
+                u32::from_str_radix(&name[2..], 16).ok().and_then(char::from_u32)
+            }
+            _ if name.starts_with('#') => {
+                name[1..].parse().ok().and_then(char::from_u32)
+            }
+            _ => None,
+        };
+        match c {
+            Some(c) => { out.push(c); rest = &rest[end + 1..]; }
+            None => { out.push('&'); rest = &rest[1..]; }
+        }
+    }
+    out.push_str(rest);
+    out
+}
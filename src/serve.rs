@@ -8,29 +8,404 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Implementation of the `serve` subcommand. Just a stub for now.
+//! Implementation of the `serve` subcommand. Builds the book, serves it over
+//! HTTP on localhost and rebuilds it whenever one of its sources changes.
+//!
+//! Open pages are told to reload through a server-sent events stream, which
+//! a small script injected at the end of every page listens to.
 
-use subcommand::Subcommand;
-use error::CliResult;
-use error::CommandResult;
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use subcommand::{parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
+use error::{self, err, CliResult, CommandResult};
 use term::Term;
+use build::{self, BuildOptions};
+use config::Config;
+use theme;
+
+const DEFAULT_PORT: u16 = 3000;
+
+/// The path of the event stream that pages listen to for reload requests.
+const RELOAD_PATH: &'static str = "/__rustbook/reload";
+
+/// Appended to every page while serving, so that it reloads itself once the
+/// book has been rebuilt.
+const RELOAD_SCRIPT: &'static str = r#"<script>
+(function() {
+    if (!window.EventSource) { return; }
+    var source = new EventSource('/__rustbook/reload');
+    source.onmessage = function() { window.location.reload(); };
+})();
+</script>"#;
 
-struct Serve;
+struct Serve {
+    book: BookArgs,
+    dest_dir: Option<PathBuf>,
+    port: u16,
+    open: bool,
+}
+
+const SERVE_OPTS: &'static [Opt] = &[
+    Opt { short: "p", long: "port", value: "PORT", help: "Port to listen on (default: 3000)" },
+    Opt { short: "d", long: "dest-dir", value: "DIR",
+          help: "Build into DIR (default: the build-dir of book.toml, or _book)" },
+    Opt { short: "o", long: "open", value: "", help: "Open the book in a browser" },
+];
 
 pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
     if name == "serve" {
-        Some(Box::new(Serve))
+        Some(Box::new(Serve {
+            book: BookArgs::new(),
+            dest_dir: None,
+            port: DEFAULT_PORT,
+            open: false,
+        }))
     } else {
         None
     }
 }
 
+/// Counts the rebuilds of the book, waking up the connections waiting on an
+/// event stream whenever it changes.
+struct Reloader {
+    generation: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl Reloader {
+    fn reload(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+}
+
 impl Subcommand for Serve {
-    fn parse_args(&mut self, _: &[String]) -> CliResult<()> {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, SERVE_OPTS])?;
+        self.book.parse(&mut matches)?;
+        if let Some(arg) = matches.free.first() {
+            return Err(err(&format!("unexpected argument `{}`", arg)));
+        }
+        if let Some(port) = matches.opt_str("port") {
+            self.port = match port.parse() {
+                Ok(port) => port,
+                Err(..) => return Err(err(&format!("invalid port number `{}`", port))),
+            };
+        }
+        self.dest_dir = matches.opt_str("dest-dir").map(PathBuf::from);
+        self.open = matches.opt_present("open");
         Ok(())
     }
-    fn usage(&self) {}
-    fn execute(&mut self, _: &mut Term) -> CommandResult<()> {
-        Ok(())
+    fn usage(&self) {
+        print_usage("rustbook serve [<src>] [options]",
+                    "Build the book in <src> (default: the current directory), serve it at \
+                     http://localhost:<port>/\nand rebuild it whenever book.toml, the theme \
+                     or a file in <src> changes.",
+                    &[BOOK_OPTS, SERVE_OPTS]);
+    }
+    fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
+        self.book.configure(term);
+        let cwd = env::current_dir()?;
+        let config_path = self.book.config_path();
+        let mut config = self.book.load_config()?;
+        if let Some(ref tgt) = self.dest_dir {
+            config.build_dir = cwd.join(tgt);
+        }
+        let tgt = config.build_dir.clone();
+
+        // A broken book is still worth serving: the error may be fixed
+        // while the server is running.
+        let options = BuildOptions { extra_postlude: RELOAD_SCRIPT, ..BuildOptions::new() };
+        if let Err(e) = build::build(&config, &options, term) {
+            term.error(&error::describe(&e));
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", self.port))?;
+        let reloader = Arc::new(Reloader {
+            generation: Mutex::new(0),
+            changed: Condvar::new(),
+        });
+        {
+            let tgt = tgt.clone();
+            let reloader = reloader.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(..) => continue,
+                    };
+                    let tgt = tgt.clone();
+                    let reloader = reloader.clone();
+                    thread::spawn(move || {
+                        // the browser going away is not our problem
+                        let _ = handle_request(stream, &tgt, &reloader);
+                    });
+                }
+            });
+        }
+        let url = format!("http://localhost:{}/", self.port);
+        term.info(&format!("Serving the book at {}", url));
+        term.info("Press Ctrl-C to stop.");
+        if self.open {
+            build::open(&url)?;
+        }
+
+        let mut last_seen = snapshot(&config_path, &config);
+        loop {
+            thread::sleep(Duration::from_secs(1));
+
+            let current = snapshot(&config_path, &config);
+            if current == last_seen { continue }
+            last_seen = current;
+
+            term.info("Change detected, rebuilding the book...");
+            let result = self.book.load_config().and_then(|new_config| {
+                // the server keeps serving from the same directory
+                config = new_config;
+                config.build_dir = tgt.clone();
+                build::build(&config, &options, term)
+            });
+            match result {
+                Ok(()) => reloader.reload(),
+                Err(e) => term.error(&error::describe(&e)),
+            }
+        }
+    }
+}
+
+/// The modification times of the configuration file `config_path`, the
+/// files of the theme and those of the book, such as its summary, its
+/// chapters and the pictures they show.
+fn snapshot(config_path: &Path, config: &Config) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut paths = vec![config_path.to_path_buf()];
+    paths.extend(theme::files(&config.theme_dir).unwrap_or(vec![]));
+    // a book being edited may come and go; what is there is watched
+    let _ = book_files(&config.src, &config.build_dir, &mut paths);
+    // the theme and the configuration file may be part of the book's files
+    paths.sort();
+    paths.dedup();
+
+    paths.into_iter().map(|path| {
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        (path, modified)
+    }).collect()
+}
+
+/// Collect the files under `dir`, in order, leaving out hidden ones and the
+/// build directory `build_dir`, which every build changes.
+fn book_files(dir: &Path, build_dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+    for path in entries {
+        let hidden = path.file_name().map_or(false, |name| {
+            name.to_string_lossy().starts_with('.')
+        });
+        if hidden || path == build_dir {
+            continue;
+        } else if path.is_dir() {
+            book_files(&path, build_dir, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream, root: &Path, reloader: &Reloader) -> io::Result<()> {
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        reader.read_line(&mut request_line)?;
+        // skip the headers, we have no use for them
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() { break }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let path = target.split(|c| c == '?' || c == '#').next().unwrap();
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"", false);
+    }
+    if path == RELOAD_PATH {
+        return stream_reloads(stream, reloader);
+    }
+
+    let file = match resolve(root, path) {
+        Some(file) => file,
+        None => return respond(&mut stream, "404 Not Found", "text/plain", b"Not Found",
+                               method == "HEAD"),
+    };
+    let mut contents = vec![];
+    match File::open(&file).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => respond(&mut stream, "200 OK", content_type(&file), &contents,
+                         method == "HEAD"),
+        Err(..) => respond(&mut stream, "404 Not Found", "text/plain", b"Not Found",
+                           method == "HEAD"),
+    }
+}
+
+fn respond(stream: &mut TcpStream,
+           status: &str,
+           content_type: &str,
+           body: &[u8],
+           head_only: bool) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\n\
+                    Content-Type: {}\r\n\
+                    Content-Length: {}\r\n\
+                    Cache-Control: no-cache\r\n\
+                    Connection: close\r\n\r\n",
+           status, content_type, body.len())?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+/// Keep the connection open as an event stream, sending an event every time
+/// the book is rebuilt.
+fn stream_reloads(mut stream: TcpStream, reloader: &Reloader) -> io::Result<()> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\n\
+                       Content-Type: text/event-stream\r\n\
+                       Cache-Control: no-cache\r\n\r\n")?;
+    stream.flush()?;
+
+    let mut seen = *reloader.generation.lock().unwrap();
+    loop {
+        let generation = {
+            let mut generation = reloader.generation.lock().unwrap();
+            while *generation == seen {
+                let (guard, timeout) = reloader.changed
+                                               .wait_timeout(generation, Duration::from_secs(15))
+                                               .unwrap();
+                generation = guard;
+                if timeout.timed_out() { break }
+            }
+            *generation
+        };
+
+        if generation == seen {
+            // a comment, so that a closed connection is eventually noticed
+            stream.write_all(b": keep-alive\n\n")?;
+        } else {
+            seen = generation;
+            stream.write_all(b"data: reload\n\n")?;
+        }
+        stream.flush()?;
+    }
+}
+
+/// Map a request path onto a file below `root`, refusing to leave it.
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = match percent_decode(path) {
+        Some(decoded) => decoded,
+        None => return None,
+    };
+
+    let mut file = root.to_path_buf();
+    for component in Path::new(decoded.trim_left_matches('/')).components() {
+        match component {
+            Component::Normal(part) => file.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    if file.is_file() { Some(file) } else { None }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 2 >= bytes.len() { return None }
+            let hex = match str::from_utf8(&bytes[i + 1..i + 3]) {
+                Ok(hex) => hex,
+                Err(..) => return None,
+            };
+            match u8::from_str_radix(hex, 16) {
+                Ok(b) => decoded.push(b),
+                Err(..) => return None,
+            }
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("woff") => "application/font-woff",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use rustc_back::tempdir::TempDir;
+
+    use config::Config;
+    use super::snapshot;
+
+    #[test]
+    fn watches_the_files_of_the_book() {
+        let tmp = TempDir::new("rustbook").unwrap();
+        let root = tmp.path();
+        for dir in &["img", "theme", ".git", "_book"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+        for file in &["SUMMARY.md", "a.md", "img/picture.png", "theme/rustbook.css",
+                      ".git/HEAD", "_book/a.html"] {
+            File::create(root.join(file)).unwrap();
+        }
+        let mut config = Config::default(root);
+        config.build_dir = root.join("_book");
+
+        let watched = snapshot(&root.join("book.toml"), &config).into_iter()
+                                                                .map(|(path, _)| path)
+                                                                .collect::<Vec<_>>();
+        let expected = ["SUMMARY.md", "a.md", "book.toml", "img/picture.png",
+                        "theme/rustbook.css"];
+        assert_eq!(watched, expected.iter().map(|p| root.join(p)).collect::<Vec<PathBuf>>());
+
+        // a missing configuration file is watched for
+        let before = snapshot(&root.join("book.toml"), &config);
+        File::create(root.join("book.toml")).unwrap();
+        assert!(snapshot(&root.join("book.toml"), &config) != before);
     }
 }
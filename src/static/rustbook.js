@@ -14,15 +14,23 @@
 document.addEventListener('DOMContentLoaded', function() {
     'use strict';
 
-    document.getElementById('toggle-nav').onclick = function(e) {
-        var toc = document.getElementById('toc');
-        var pagewrapper = document.getElementById('page-wrapper');
-        toggleClass(toc, 'mobile-hidden');
-        toggleClass(pagewrapper, 'mobile-hidden');
-    };
+    // A theme's table of contents may do without the button, and its pages
+    // without either of the elements it shows and hides.
+    var toggleNav = document.getElementById('toggle-nav');
+    if (toggleNav) {
+        toggleNav.onclick = function(e) {
+            var toc = document.getElementById('toc');
+            var pagewrapper = document.getElementById('page-wrapper');
+            toggleClass(toc, 'mobile-hidden');
+            toggleClass(pagewrapper, 'mobile-hidden');
+        };
+    }
 
     function toggleClass(el, className) {
         // from http://youmightnotneedjquery.com/
+        if (!el) {
+            return;
+        }
         if (el.classList) {
             el.classList.toggle(className);
         } else {
@@ -39,40 +47,266 @@ document.addEventListener('DOMContentLoaded', function() {
         }
     }
 
-    // The below code is used to add prev and next navigation links to the
-    // bottom of each of the sections.
-    // It works by extracting the current page based on the url and iterates
-    // over the menu links until it finds the menu item for the current page. We
-    // then create a copy of the preceding and following menu links and add the
-    // correct css class and insert them into the bottom of the page.
-    var toc = document.getElementById('toc').getElementsByTagName('a');
-    var href = document.location.pathname.split('/').pop();
+    // The below code implements searching the book. The index, written by
+    // `rustbook build` next to this script, is only loaded once the search
+    // box is first used. Results link to the sections they were found in,
+    // and the terms searched for are highlighted there. Themes without a
+    // search box go without.
+    var search = document.getElementById('search');
+    var searchBox = document.getElementById('search-box');
+    var searchResults = document.getElementById('search-results');
+    var pathToRoot = search ? search.getAttribute('data-path-to-root') : '';
+    var searchIndex = null;
+    var selected = -1;
+    var maxResults = 30;
+
+    function escapeHtml(s) {
+        return s.replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;')
+                .replace(/"/g, '&quot;').replace(/'/g, '&#39;');
+    }
+
+    function escapeRegExp(s) {
+        return s.replace(/[.*+?^${}()|[\]\\\/]/g, '\\$&');
+    }
+
+    function searchTerms(query) {
+        return query.toLowerCase().split(/\s+/).filter(function(term) {
+            return term.length > 0;
+        });
+    }
+
+    // A regular expression matching any of `terms`, for highlighting them.
+    function termsPattern(terms, escape) {
+        var alternatives = terms.map(function(term) {
+            return escapeRegExp(escape ? escapeHtml(term) : term);
+        });
+        return new RegExp(alternatives.join('|'), 'gi');
+    }
 
-    if (href === 'index.html' || href === '') {
-        href = 'README.html';
+    function loadIndex() {
+        if (searchIndex !== null || document.getElementById('search-index')) {
+            return;
+        }
+        var script = document.createElement('script');
+        script.id = 'search-index';
+        script.src = pathToRoot + 'searchindex.js';
+        script.onload = function() {
+            searchIndex = window.searchIndex.map(function(entry) {
+                entry.lowerChapter = entry.chapter.toLowerCase();
+                entry.lowerHeading = entry.heading.toLowerCase();
+                entry.lowerText = entry.text.toLowerCase();
+                return entry;
+            });
+            showResults();
+        };
+        document.body.appendChild(script);
     }
 
-    for (var i = 0; i < toc.length; i++) {
-        if (toc[i].attributes.href.value.split('/').pop() === href) {
-            var nav = document.createElement('p');
+    function occurrences(haystack, term) {
+        var count = 0;
+        var i = haystack.indexOf(term);
+        while (i >= 0) {
+            count++;
+            i = haystack.indexOf(term, i + term.length);
+        }
+        return count;
+    }
 
-            if (i > 0) {
-                var prevNode = toc[i-1].cloneNode(true);
-                prevNode.className = 'left';
-                prevNode.setAttribute('rel', 'prev');
-                nav.appendChild(prevNode);
+    // Every section containing all of the terms, the sections whose
+    // headings and chapters match them coming first.
+    function find(terms) {
+        var results = [];
+        searchIndex.forEach(function(entry, position) {
+            var score = 0;
+            for (var i = 0; i < terms.length; i++) {
+                var found = 10 * occurrences(entry.lowerHeading, terms[i]) +
+                            5 * occurrences(entry.lowerChapter, terms[i]) +
+                            occurrences(entry.lowerText, terms[i]);
+                if (found === 0) {
+                    return;
+                }
+                score += found;
             }
+            results.push({ entry: entry, score: score, position: position });
+        });
+        results.sort(function(a, b) {
+            return b.score - a.score || a.position - b.position;
+        });
+        return results.slice(0, maxResults);
+    }
 
-            if (i < toc.length - 1) {
-                var nextNode = toc[i+1].cloneNode(true);
-                nextNode.className = 'right';
-                nextNode.setAttribute('rel', 'next');
-                nav.appendChild(nextNode);
+    // A piece of the text of `entry` around the first of the terms in it,
+    // with the terms highlighted.
+    function snippet(entry, terms) {
+        var first = entry.text.length;
+        terms.forEach(function(term) {
+            var i = entry.lowerText.indexOf(term);
+            if (i >= 0 && i < first) {
+                first = i;
             }
+        });
+        if (first === entry.text.length) {
+            first = 0;
+        }
+        var start = Math.max(0, first - 60);
+        if (start > 0) {
+            start = entry.text.indexOf(' ', start) + 1 || start;
+        }
+        var end = Math.min(entry.text.length, start + 180);
+        var text = escapeHtml(entry.text.slice(start, end));
+        text = text.replace(termsPattern(terms, true), '<mark>$&</mark>');
+        return (start > 0 ? '&hellip;' : '') + text +
+               (end < entry.text.length ? '&hellip;' : '');
+    }
+
+    function showResults() {
+        var terms = searchTerms(searchBox.value);
+        selected = -1;
+        if (terms.length === 0 || searchIndex === null) {
+            searchResults.innerHTML = '';
+            searchResults.className = 'hidden';
+            return;
+        }
+        var results = find(terms);
+        var highlight = '?highlight=' + encodeURIComponent(terms.join(' '));
+        var html = results.map(function(result) {
+            var entry = result.entry;
+            var href = pathToRoot + entry.page + highlight +
+                       (entry.anchor ? '#' + encodeURIComponent(entry.anchor) : '');
+            var title = (entry.section ? entry.section + ' ' : '') + entry.chapter +
+                        (entry.heading ? ' \u00bb ' + entry.heading : '');
+            return '<li><a href="' + escapeHtml(href) + '">' +
+                   '<span class="search-title">' + escapeHtml(title) + '</span>' +
+                   '<span class="search-snippet">' + snippet(entry, terms) + '</span>' +
+                   '</a></li>';
+        });
+        if (html.length === 0) {
+            html.push('<li class="search-none">No results</li>');
+        }
+        searchResults.innerHTML = html.join('');
+        searchResults.className = '';
+    }
 
-            document.getElementById('page').appendChild(nav);
+    function select(i) {
+        var links = searchResults.getElementsByTagName('a');
+        if (links.length === 0) {
+            return;
+        }
+        if (selected >= 0) {
+            links[selected].className = '';
+        }
+        selected = Math.max(0, Math.min(i, links.length - 1));
+        links[selected].className = 'selected';
+        links[selected].scrollIntoView(false);
+    }
 
-            break;
+    function clearSearch() {
+        searchBox.value = '';
+        showResults();
+        searchBox.blur();
+    }
+
+    if (search && searchBox && searchResults) {
+        searchBox.addEventListener('focus', loadIndex);
+        searchBox.addEventListener('input', showResults);
+        searchBox.addEventListener('keydown', function(e) {
+            var links = searchResults.getElementsByTagName('a');
+            if (e.key === 'ArrowDown' || e.keyCode === 40) {
+                select(selected + 1);
+            } else if (e.key === 'ArrowUp' || e.keyCode === 38) {
+                select(selected - 1);
+            } else if (e.key === 'Enter' || e.keyCode === 13) {
+                if (links.length > 0) {
+                    window.location.href = links[Math.max(selected, 0)].href;
+                }
+            } else if (e.key === 'Escape' || e.keyCode === 27) {
+                clearSearch();
+            } else {
+                return;
+            }
+            e.preventDefault();
+        });
+    }
+
+    // `S` or `/` jumps to the search box, Escape gets rid of the highlighted
+    // search terms, and the left and right arrow keys go to the previous and
+    // next chapters, which every page links to in its <head>.
+    document.addEventListener('keydown', function(e) {
+        var target = e.target.tagName;
+        if (e.ctrlKey || e.altKey || e.metaKey || target === 'INPUT' ||
+            target === 'TEXTAREA' || target === 'SELECT' || e.target.isContentEditable) {
+            return;
+        }
+        if (e.key === 's' || e.key === 'S' || e.key === '/' ||
+            e.keyCode === 83 || e.keyCode === 191) {
+            if (searchBox) {
+                e.preventDefault();
+                searchBox.focus();
+            }
+        } else if (e.key === 'Escape' || e.keyCode === 27) {
+            removeHighlights();
+        } else if (!e.shiftKey && (e.key === 'ArrowLeft' || e.keyCode === 37)) {
+            followLink('prev');
+        } else if (!e.shiftKey && (e.key === 'ArrowRight' || e.keyCode === 39)) {
+            followLink('next');
+        }
+    });
+
+    function followLink(rel) {
+        var link = document.querySelector('link[rel="' + rel + '"]');
+        if (link) {
+            window.location.href = link.href;
+        }
+    }
+
+    function highlight(node, pattern) {
+        if (node.nodeType === 3) {
+            var text = node.nodeValue;
+            pattern.lastIndex = 0;
+            var match = pattern.exec(text);
+            if (!match) {
+                return;
+            }
+            var fragment = document.createDocumentFragment();
+            var last = 0;
+            while (match) {
+                fragment.appendChild(document.createTextNode(text.slice(last, match.index)));
+                var mark = document.createElement('mark');
+                mark.className = 'search-highlight';
+                mark.appendChild(document.createTextNode(match[0]));
+                fragment.appendChild(mark);
+                last = match.index + match[0].length;
+                match = pattern.exec(text);
+            }
+            fragment.appendChild(document.createTextNode(text.slice(last)));
+            node.parentNode.replaceChild(fragment, node);
+        } else if (node.nodeType === 1 && !/^(SCRIPT|STYLE|MARK)$/.test(node.tagName)) {
+            var children = Array.prototype.slice.call(node.childNodes);
+            for (var i = 0; i < children.length; i++) {
+                highlight(children[i], pattern);
+            }
+        }
+    }
+
+    function removeHighlights() {
+        var marks = document.querySelectorAll('mark.search-highlight');
+        for (var i = 0; i < marks.length; i++) {
+            var parent = marks[i].parentNode;
+            parent.replaceChild(document.createTextNode(marks[i].textContent), marks[i]);
+            parent.normalize();
+        }
+    }
+
+    var query = /[?&]highlight=([^&#]*)/.exec(document.location.search);
+    var page = document.getElementById('page');
+    if (query && page) {
+        var terms = searchTerms(decodeURIComponent(query[1].replace(/\+/g, ' ')));
+        if (terms.length > 0) {
+            highlight(page, termsPattern(terms, false));
+            var firstMark = document.querySelector('mark.search-highlight');
+            if (firstMark && !document.location.hash) {
+                firstMark.scrollIntoView();
+            }
         }
     }
 });
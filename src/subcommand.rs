@@ -10,17 +10,25 @@
 
 //! Common API for all rustbook subcommands.
 
-use error::CliResult;
+use std::env;
+use std::path::PathBuf;
+
+use config::{Config, CONFIG_FILE};
+use error::{err, CliResult};
 use error::CommandResult;
-use term::Term;
+use term::{ColorChoice, Term, Verbosity};
 
 use help;
+use init;
 use build;
 use serve;
 use test;
+use linkcheck;
+use theme;
 
 pub trait Subcommand {
-    /// Mutate the subcommand by parsing its arguments.
+    /// Mutate the subcommand by parsing its arguments, i.e. everything on
+    /// the command line after the subcommand's name.
     ///
     /// Returns `Err` on a parsing error.
     fn parse_args(&mut self, args: &[String]) -> CliResult<()>;
@@ -32,13 +40,324 @@ pub trait Subcommand {
 
 /// Create a Subcommand object based on its name.
 pub fn parse_name(name: &str) -> Option<Box<Subcommand>> {
-    let cmds: [fn(&str) -> Option<Box<Subcommand>>; 4] = [help::parse_cmd,
+    let cmds: [fn(&str) -> Option<Box<Subcommand>>; 7] = [help::parse_cmd,
+                                                          init::parse_cmd,
                                                           build::parse_cmd,
                                                           serve::parse_cmd,
-                                                          test::parse_cmd];
+                                                          test::parse_cmd,
+                                                          linkcheck::parse_cmd,
+                                                          theme::parse_cmd];
     for parser in &cmds {
         let parsed = (*parser)(name);
         if parsed.is_some() { return parsed }
     }
     None
 }
+
+/// A command line option accepted by a subcommand.
+pub struct Opt {
+    /// The short name, e.g. `"v"` for `-v`, or `""` if there is none.
+    pub short: &'static str,
+    /// The long name, e.g. `"verbose"` for `--verbose`.
+    pub long: &'static str,
+    /// The name of the option's value, or `""` for flags.
+    pub value: &'static str,
+    pub help: &'static str,
+}
+
+/// Options shared by the subcommands that work on a book.
+pub const BOOK_OPTS: &'static [Opt] = &[
+    Opt { short: "s", long: "src", value: "DIR",
+          help: "The book's directory (default: the current directory)" },
+    Opt { short: "c", long: "config", value: "FILE",
+          help: "Read the configuration from FILE instead of <src>/book.toml" },
+    Opt { short: "v", long: "verbose", value: "", help: "Print more about what is going on" },
+    Opt { short: "q", long: "quiet", value: "", help: "Only print errors" },
+    Opt { short: "", long: "color", value: "WHEN",
+          help: "Color errors and warnings: auto, always or never (default: auto)" },
+];
+
+/// The values of `BOOK_OPTS`, along with the book's directory if it was
+/// given as an argument instead.
+pub struct BookArgs {
+    pub src: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub verbosity: Verbosity,
+    pub color: ColorChoice,
+}
+
+impl BookArgs {
+    pub fn new() -> BookArgs {
+        BookArgs {
+            src: None,
+            config: None,
+            verbosity: Verbosity::Normal,
+            color: ColorChoice::Auto,
+        }
+    }
+
+    /// Take the values of `BOOK_OPTS` from `matches`, along with the book's
+    /// directory from the first free argument, which is consumed.
+    pub fn parse(&mut self, matches: &mut Matches) -> CliResult<()> {
+        self.src = matches.opt_str("src").map(PathBuf::from);
+        if !matches.free.is_empty() {
+            if self.src.is_some() {
+                return Err(err("the book's directory was given twice"));
+            }
+            self.src = Some(PathBuf::from(matches.free.remove(0)));
+        }
+        self.config = matches.opt_str("config").map(PathBuf::from);
+        self.verbosity = match (matches.opt_present("verbose"), matches.opt_present("quiet")) {
+            (true, true) => return Err(err("`--verbose` and `--quiet` cannot be combined")),
+            (true, false) => Verbosity::Verbose,
+            (false, true) => Verbosity::Quiet,
+            (false, false) => Verbosity::Normal,
+        };
+        self.color = match matches.opt_str("color").as_ref().map(|c| &c[..]) {
+            None | Some("auto") => ColorChoice::Auto,
+            Some("always") => ColorChoice::Always,
+            Some("never") => ColorChoice::Never,
+            Some(color) => {
+                return Err(err(&format!("`--color` must be auto, always or never, not `{}`",
+                                        color)));
+            }
+        };
+        Ok(())
+    }
+
+    /// Make `term` as verbose and colorful as asked for.
+    pub fn configure(&self, term: &mut Term) {
+        term.set_verbosity(self.verbosity);
+        term.set_color(self.color);
+    }
+
+    /// The directory of the book.
+    pub fn root(&self) -> PathBuf {
+        let cwd = env::current_dir().unwrap();
+        match self.src {
+            Some(ref src) => cwd.join(src),
+            None => cwd,
+        }
+    }
+
+    /// The file the configuration of the book is read from, which need not
+    /// exist unless given with `--config`.
+    pub fn config_path(&self) -> PathBuf {
+        match self.config {
+            Some(ref file) => env::current_dir().unwrap().join(file),
+            None => self.root().join(CONFIG_FILE),
+        }
+    }
+
+    /// Load the configuration of the book.
+    pub fn load_config(&self) -> CliResult<Config> {
+        match self.config {
+            Some(ref file) => Config::from_file(file, &self.root()),
+            None => Config::load(&self.root()),
+        }
+    }
+}
+
+/// The result of parsing the arguments of a subcommand.
+pub struct Matches {
+    opts: Vec<(&'static str, Option<String>)>,
+    /// The arguments that are not options.
+    pub free: Vec<String>,
+    /// How many of `free` came before `--`, if it was given.
+    pub dashes: Option<usize>,
+}
+
+impl Matches {
+    /// Whether the option with the long name `long` was given.
+    pub fn opt_present(&self, long: &str) -> bool {
+        self.opts.iter().any(|&(name, _)| name == long)
+    }
+
+    /// The value of the last occurrence of the option `long`.
+    pub fn opt_str(&self, long: &str) -> Option<String> {
+        self.opt_strs(long).pop()
+    }
+
+    /// The values of every occurrence of the option `long`.
+    pub fn opt_strs(&self, long: &str) -> Vec<String> {
+        self.opts.iter()
+                 .filter(|&&(name, _)| name == long)
+                 .filter_map(|&(_, ref value)| value.clone())
+                 .collect()
+    }
+}
+
+/// Parse the value of a `--jobs` option, a number of threads.
+pub fn parse_jobs(jobs: &str) -> CliResult<usize> {
+    match jobs.parse() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err(err(&format!("invalid number of jobs `{}`", jobs))),
+    }
+}
+
+/// Parse `args` according to the option groups in `groups`. Options may be
+/// given as `--long value`, `--long=value`, `-s value` or `-svalue`, short
+/// flags may be bundled as in `-vq`, and `--` ends them.
+pub fn parse_opts(args: &[String], groups: &[&[Opt]]) -> CliResult<Matches> {
+    let mut matches = Matches { opts: vec![], free: vec![], dashes: None };
+    let mut args = args.iter();
+    let find = |arg: &str, long_form: bool, name: &str| {
+        groups.iter().flat_map(|group| group.iter()).find(|opt| {
+            if long_form { opt.long == name } else { opt.short == name }
+        }).ok_or_else(|| err(&format!("unrecognized option `{}`", arg)))
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            matches.dashes = Some(matches.free.len());
+            matches.free.extend(args.cloned());
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            matches.free.push(arg.clone());
+            continue;
+        }
+
+        if arg.starts_with("--") {
+            let (name, inline_value) = match arg.find('=') {
+                Some(i) => (&arg[2..i], Some(arg[i + 1..].to_string())),
+                None => (&arg[2..], None),
+            };
+            let opt = find(arg, true, name)?;
+            let value = if opt.value.is_empty() {
+                if inline_value.is_some() {
+                    return Err(err(&format!("option `--{}` does not take a value", opt.long)));
+                }
+                None
+            } else {
+                match inline_value.or_else(|| args.next().cloned()) {
+                    Some(value) => Some(value),
+                    None => return Err(err(&format!("option `--{}` requires a value", opt.long))),
+                }
+            };
+            matches.opts.push((opt.long, value));
+            continue;
+        }
+
+        // a run of short flags, the last of which may take the rest of the
+        // argument, or else the next one, as its value
+        let mut rest = &arg[1..];
+        while let Some(c) = rest.chars().next() {
+            let opt = find(arg, false, &rest[..c.len_utf8()])?;
+            rest = &rest[c.len_utf8()..];
+            if opt.value.is_empty() {
+                matches.opts.push((opt.long, None));
+                continue;
+            }
+            let value = if rest.is_empty() { args.next().cloned() } else { Some(rest.to_string()) };
+            match value {
+                Some(value) => matches.opts.push((opt.long, Some(value))),
+                None => return Err(err(&format!("option `--{}` requires a value", opt.long))),
+            }
+            break;
+        }
+    }
+    Ok(matches)
+}
+
+/// Print the usage information of a subcommand: a usage line, a description
+/// and its options.
+pub fn print_usage(usage: &str, description: &str, groups: &[&[Opt]]) {
+    println!("Usage: {}", usage);
+    println!("");
+    println!("{}", description);
+
+    let flags = groups.iter().flat_map(|group| group.iter()).map(|opt| {
+        let short = if opt.short.is_empty() {
+            "    ".to_string()
+        } else {
+            format!("-{}, ", opt.short)
+        };
+        let value = if opt.value.is_empty() {
+            "".to_string()
+        } else {
+            format!(" <{}>", opt.value)
+        };
+        (format!("{}--{}{}", short, opt.long, value), opt.help)
+    }).collect::<Vec<_>>();
+    if flags.is_empty() { return }
+
+    let width = flags.iter().map(|&(ref flag, _)| flag.len()).max().unwrap();
+    println!("");
+    println!("Options:");
+    for (flag, help) in flags {
+        println!("  {:2$}    {}", flag, help, width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_opts, Matches, Opt, BOOK_OPTS};
+
+    const OPTS: &'static [Opt] = &[
+        Opt { short: "j", long: "jobs", value: "N", help: "" },
+        Opt { short: "", long: "skip", value: "FILTER", help: "" },
+    ];
+
+    fn parse(args: &[&str]) -> Result<Matches, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        parse_opts(&args, &[BOOK_OPTS, OPTS]).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn values() {
+        for args in &[&["--jobs=4"][..], &["--jobs", "4"], &["-j4"], &["-j", "4"]] {
+            assert_eq!(parse(args).ok().unwrap().opt_str("jobs"), Some("4".to_string()));
+        }
+        // a value may look like an option
+        assert_eq!(parse(&["--skip", "-v"]).ok().unwrap().opt_str("skip"), Some("-v".to_string()));
+        assert_eq!(parse(&["--skip="]).ok().unwrap().opt_str("skip"), Some("".to_string()));
+
+        assert!(parse(&["--jobs"]).err().unwrap().contains("`--jobs` requires a value"));
+        assert!(parse(&["-j"]).err().unwrap().contains("`--jobs` requires a value"));
+        assert!(parse(&["--quiet=yes"]).err().unwrap().contains("does not take a value"));
+    }
+
+    #[test]
+    fn unknown_options() {
+        assert!(parse(&["--frobnicate"]).err().unwrap().contains("unrecognized option"));
+        assert!(parse(&["--job=4"]).err().unwrap().contains("`--job=4`"));
+        assert!(parse(&["-x"]).err().unwrap().contains("`-x`"));
+        // `--skip` has no short name
+        assert!(parse(&["-k", "a"]).err().unwrap().contains("`-k`"));
+    }
+
+    #[test]
+    fn bundled_flags() {
+        let matches = parse(&["-vq"]).ok().unwrap();
+        assert!(matches.opt_present("verbose") && matches.opt_present("quiet"));
+        let matches = parse(&["-vj4", "-qs", "book"]).ok().unwrap();
+        assert!(matches.opt_present("verbose") && matches.opt_present("quiet"));
+        assert_eq!(matches.opt_str("jobs"), Some("4".to_string()));
+        assert_eq!(matches.opt_str("src"), Some("book".to_string()));
+        assert!(parse(&["-vx"]).err().unwrap().contains("`-vx`"));
+        assert!(parse(&["-vj"]).err().unwrap().contains("`--jobs` requires a value"));
+    }
+
+    #[test]
+    fn repeated_options() {
+        let matches = parse(&["--skip", "a", "-j1", "--skip=b", "--jobs", "2"]).ok().unwrap();
+        assert_eq!(matches.opt_strs("skip"), ["a", "b"]);
+        assert_eq!(matches.opt_str("jobs"), Some("2".to_string()));
+        assert!(!matches.opt_present("verbose"));
+    }
+
+    #[test]
+    fn dashes() {
+        let matches = parse(&["a", "-v", "-", "b"]).ok().unwrap();
+        assert_eq!(matches.free, ["a", "-", "b"]);
+        assert_eq!(matches.dashes, None);
+        let matches = parse(&["a", "--", "-v", "--", "b"]).ok().unwrap();
+        assert_eq!(matches.free, ["a", "-v", "--", "b"]);
+        assert_eq!(matches.dashes, Some(1));
+        assert!(!matches.opt_present("verbose"));
+        let matches = parse(&["--skip", "--", "--"]).ok().unwrap();
+        assert_eq!(matches.opt_str("skip"), Some("--".to_string()));
+        assert_eq!((matches.free.len(), matches.dashes), (0, Some(0)));
+    }
+}
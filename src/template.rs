@@ -0,0 +1,325 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The templates of a theme, which the layout of every page is made from.
+//! They are HTML with a few kinds of tags in double braces:
+//!
+//! * `{{name}}` or `{{name.field}}` inserts a value of the context, escaped
+//!   unless it is HTML already;
+//! * `{{#if name}}...{{else}}...{{/if}}` shows its first part if the value is
+//!   there and not empty, and its `else` part, if any, otherwise;
+//! * `{{> name}}` inserts the template `name`, such as `footer.html`;
+//! * `{{! comment}}` is left out.
+
+use markdown;
+
+/// A value of the context a template is filled in with.
+pub enum Value {
+    /// Text, which is escaped when inserted.
+    Text(String),
+    /// HTML, which is inserted as it is.
+    Html(String),
+    /// Named values, reached with `{{name.field}}`.
+    Map(Vec<(&'static str, Value)>),
+    /// A value that is not there this time, such as the previous chapter of
+    /// the first one. It inserts nothing.
+    Nothing,
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Text(ref s) | Value::Html(ref s) => !s.is_empty(),
+            Value::Map(..) => true,
+            Value::Nothing => false,
+        }
+    }
+}
+
+enum Node {
+    Text(String),
+    Variable { path: String, line: usize },
+    If { path: String, line: usize, then: Vec<Node>, otherwise: Vec<Node> },
+    Partial { name: String, line: usize },
+}
+
+pub struct Template {
+    /// What to call the template in errors, such as its path.
+    name: String,
+    nodes: Vec<Node>,
+}
+
+/// A `{{#if}}` whose `{{/if}}` has not been reached yet.
+struct OpenIf {
+    path: String,
+    line: usize,
+    then: Vec<Node>,
+    in_else: bool,
+}
+
+/// How deeply templates may include each other, which stops a template
+/// including itself.
+const MAX_DEPTH: usize = 16;
+
+impl Template {
+    /// Parse the template `source`, called `name` in errors.
+    pub fn parse(name: &str, source: &str) -> Result<Template, String> {
+        let error = |line: usize, message: &str| format!("{}:{}: {}", name, line, message);
+
+        let mut nodes = vec![];
+        let mut open: Vec<(OpenIf, Vec<Node>)> = vec![];
+        let mut line = 1;
+        let mut rest = source;
+        loop {
+            let start = rest.find("{{").unwrap_or(rest.len());
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+                line += rest[..start].matches('\n').count();
+            }
+            if start == rest.len() { break }
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => return Err(error(line, "unclosed `{{`")),
+            };
+            let tag = rest[start + 2..end].trim();
+            let tag_line = line;
+            line += rest[start..end].matches('\n').count();
+            rest = &rest[end + 2..];
+
+            if tag.starts_with('!') {
+                continue;
+            } else if tag.starts_with("#if ") {
+                let path = variable(&tag[4..]).ok_or_else(|| error(tag_line, "invalid `#if`"))?;
+                let block = OpenIf { path: path, line: tag_line, then: vec![], in_else: false };
+                open.push((block, nodes));
+                nodes = vec![];
+            } else if tag == "else" {
+                match open.last_mut() {
+                    Some(&mut (ref mut block, _)) if !block.in_else => {
+                        block.then = ::std::mem::replace(&mut nodes, vec![]);
+                        block.in_else = true;
+                    }
+                    _ => return Err(error(tag_line, "`else` outside of `#if`")),
+                }
+            } else if tag == "/if" {
+                let (block, outer) = match open.pop() {
+                    Some(open) => open,
+                    None => return Err(error(tag_line, "`/if` without `#if`")),
+                };
+                let (then, otherwise) = if block.in_else {
+                    (block.then, nodes)
+                } else {
+                    (nodes, vec![])
+                };
+                nodes = outer;
+                nodes.push(Node::If {
+                    path: block.path,
+                    line: block.line,
+                    then: then,
+                    otherwise: otherwise,
+                });
+            } else if tag.starts_with('>') {
+                let partial = tag[1..].trim();
+                if partial.is_empty() {
+                    return Err(error(tag_line, "`>` without a template to insert"));
+                }
+                nodes.push(Node::Partial { name: partial.to_string(), line: tag_line });
+            } else {
+                match variable(tag) {
+                    Some(path) => nodes.push(Node::Variable { path: path, line: tag_line }),
+                    None => return Err(error(tag_line, &format!("invalid tag `{{{{{}}}}}`", tag))),
+                }
+            }
+        }
+        if let Some((block, _)) = open.pop() {
+            return Err(error(block.line, "`#if` without `/if`"));
+        }
+        Ok(Template { name: name.to_string(), nodes: nodes })
+    }
+
+    /// Fill in the template with the values of `context`, a `Value::Map`.
+    /// `partials` finds the templates it may insert, by name.
+    pub fn render<'a, F>(&self, context: &Value, partials: &F) -> Result<String, String>
+        where F: Fn(&str) -> Option<&'a Template>
+    {
+        let mut out = String::new();
+        self.render_nodes(&self.nodes, context, partials, 0, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_nodes<'a, F>(&self,
+                           nodes: &[Node],
+                           context: &Value,
+                           partials: &F,
+                           depth: usize,
+                           out: &mut String) -> Result<(), String>
+        where F: Fn(&str) -> Option<&'a Template>
+    {
+        let error = |line: usize, message: &str| format!("{}:{}: {}", self.name, line, message);
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => out.push_str(text),
+                Node::Variable { ref path, line } => {
+                    match lookup(context, path).map_err(|e| error(line, &e))? {
+                        &Value::Text(ref s) => out.push_str(&markdown::escape_html(s)),
+                        &Value::Html(ref s) => out.push_str(s),
+                        &Value::Nothing => {}
+                        &Value::Map(..) => {
+                            return Err(error(line, &format!("`{}` cannot be inserted, only \
+                                                             its fields", path)));
+                        }
+                    }
+                }
+                Node::If { ref path, line, ref then, ref otherwise } => {
+                    let value = lookup(context, path).map_err(|e| error(line, &e))?;
+                    let nodes = if value.is_truthy() { then } else { otherwise };
+                    self.render_nodes(nodes, context, partials, depth, out)?;
+                }
+                Node::Partial { ref name, line } => {
+                    let partial = match partials(name) {
+                        Some(partial) => partial,
+                        None => return Err(error(line, &format!("no template `{}`", name))),
+                    };
+                    if depth >= MAX_DEPTH {
+                        return Err(error(line, &format!("templates include each other too \
+                                                         deeply, at `{}`", name)));
+                    }
+                    partial.render_nodes(&partial.nodes, context, partials, depth + 1, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of a variable, such as `prev.title`, if `s` is one.
+fn variable(s: &str) -> Option<String> {
+    let s = s.trim();
+    let valid = !s.is_empty() && s.split('.').all(|part| {
+        !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    });
+    if valid { Some(s.to_string()) } else { None }
+}
+
+/// The value of the variable `path` in `context`. A field of a value that is
+/// not there is not there either.
+fn lookup<'a>(context: &'a Value, path: &str) -> Result<&'a Value, String> {
+    let mut value = context;
+    let mut seen = String::new();
+    for part in path.split('.') {
+        value = match *value {
+            Value::Map(ref fields) => {
+                match fields.iter().find(|&&(name, _)| name == part) {
+                    Some(&(_, ref field)) => field,
+                    None if seen.is_empty() => {
+                        return Err(format!("unknown variable `{}`", part));
+                    }
+                    None => return Err(format!("`{}` has no field `{}`", seen, part)),
+                }
+            }
+            Value::Nothing => return Ok(value),
+            _ => return Err(format!("`{}` has no field `{}`", seen, part)),
+        };
+        if !seen.is_empty() { seen.push('.') }
+        seen.push_str(part);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Template, Value};
+
+    fn context() -> Value {
+        Value::Map(vec![
+            ("title", Value::Text("<A & B>".to_string())),
+            ("toc", Value::Html("<ol></ol>".to_string())),
+            ("empty", Value::Text(String::new())),
+            ("next", Value::Nothing),
+            ("prev", Value::Map(vec![("title", Value::Text("Before".to_string()))])),
+        ])
+    }
+
+    /// Render `source`, with `partials` as the other templates.
+    fn render(source: &str, partials: &[(&str, &str)]) -> Result<String, String> {
+        let partials: Vec<(&str, Template)> = partials.iter().map(|&(name, source)| {
+            (name, Template::parse(name, source).unwrap())
+        }).collect();
+        let template = Template::parse("page.html", source)?;
+        template.render(&context(), &|name: &str| {
+            partials.iter().find(|&&(known, _)| known == name).map(|&(_, ref t)| t)
+        })
+    }
+
+    #[test]
+    fn escapes_text_but_not_html() {
+        assert_eq!(render("<h1>{{ title }}</h1>{{toc}}", &[]).unwrap(),
+                   "<h1>&lt;A &amp; B&gt;</h1><ol></ol>");
+        assert_eq!(render("{{prev.title}}{{! a comment }}!", &[]).unwrap(), "Before!");
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "{{#if prev}}<a>{{prev.title}}</a>{{else}}none{{/if}}";
+        assert_eq!(render(source, &[]).unwrap(), "<a>Before</a>");
+        let source = "{{#if next}}<a>{{next.title}}</a>{{else}}none{{/if}}";
+        assert_eq!(render(source, &[]).unwrap(), "none");
+        assert_eq!(render("{{#if empty}}x{{/if}}{{#if title}}y{{#if toc}}z{{/if}}{{/if}}", &[])
+                       .unwrap(),
+                   "yz");
+    }
+
+    #[test]
+    fn fields_of_missing_values_are_missing() {
+        assert_eq!(render("[{{next.title}}]", &[]).unwrap(), "[]");
+        assert_eq!(render("{{#if next.title}}x{{else}}y{{/if}}", &[]).unwrap(), "y");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |source: &str| Template::parse("page.html", source).err().unwrap();
+        assert_eq!(error("a\n{{title"), "page.html:2: unclosed `{{`");
+        assert_eq!(error("{{#if title}}\n\n"), "page.html:1: `#if` without `/if`");
+        assert_eq!(error("{{#if title}}{{/if}}\n{{/if}}"), "page.html:2: `/if` without `#if`");
+        assert_eq!(error("{{else}}"), "page.html:1: `else` outside of `#if`");
+        assert_eq!(error("{{#if a}}{{else}}{{else}}{{/if}}"),
+                   "page.html:1: `else` outside of `#if`");
+        assert_eq!(error("{{#if}}{{/if}}"), "page.html:1: invalid tag `{{#if}}`");
+        assert_eq!(error("{{#if a b}}{{/if}}"), "page.html:1: invalid `#if`");
+        assert_eq!(error("{{>}}"), "page.html:1: `>` without a template to insert");
+        assert_eq!(error("{{a-b}}"), "page.html:1: invalid tag `{{a-b}}`");
+    }
+
+    #[test]
+    fn render_errors() {
+        assert_eq!(render("\n{{titel}}", &[]).err().unwrap(),
+                   "page.html:2: unknown variable `titel`");
+        assert_eq!(render("{{prev.link}}", &[]).err().unwrap(),
+                   "page.html:1: `prev` has no field `link`");
+        assert_eq!(render("{{title.text}}", &[]).err().unwrap(),
+                   "page.html:1: `title` has no field `text`");
+        assert_eq!(render("{{prev}}", &[]).err().unwrap(),
+                   "page.html:1: `prev` cannot be inserted, only its fields");
+    }
+
+    #[test]
+    fn partials() {
+        let partials = [("footer.html", "<footer>{{title}}</footer>")];
+        assert_eq!(render("<main></main>{{> footer.html }}", &partials).unwrap(),
+                   "<main></main><footer>&lt;A &amp; B&gt;</footer>");
+        assert_eq!(render("\n{{> header.html}}", &partials).err().unwrap(),
+                   "page.html:2: no template `header.html`");
+        let partials = [("a.html", "{{> b.html}}"), ("b.html", "{{> a.html}}")];
+        // the page is at depth 0, so `b.html` is at MAX_DEPTH when it gets
+        // to include `a.html` once more
+        assert_eq!(render("{{> a.html}}", &partials).err().unwrap(),
+                   "b.html:1: templates include each other too deeply, at `a.html`");
+    }
+}
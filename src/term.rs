@@ -8,27 +8,151 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! An abstraction of the terminal. Eventually, provide color and
-//! verbosity support. For now, just a wrapper around stdout/stderr.
+//! An abstraction of the terminal. Status messages go to stdout, and errors,
+//! warnings, notes and progress to stderr, where their prefixes are colored
+//! when it is a terminal.
 
+use std::env;
 use std::io;
 use std::io::prelude::*;
-use std::sync::atomic::Ordering;
+
+/// How much a subcommand should say about what it is doing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Whether to color the output, from `--color`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorChoice {
+    /// Only when stderr is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+const RED: &'static str = "\x1b[1;31m";
+const YELLOW: &'static str = "\x1b[1;33m";
+const CYAN: &'static str = "\x1b[1;36m";
+const RESET: &'static str = "\x1b[0m";
 
 pub struct Term {
-    err: Box<Write + 'static>
+    err: Box<Write + 'static>,
+    verbosity: Verbosity,
+    color: bool,
+    /// Whether stderr is a terminal, where progress is shown on a line of
+    /// its own that is overwritten as it goes.
+    tty: bool,
+    /// Whether that line is showing, and must be cleared before anything
+    /// else is printed.
+    progress: bool,
 }
 
 impl Term {
     pub fn new() -> Term {
+        let tty = stderr_is_tty();
         Term {
-            err: Box::new(io::stderr())
+            err: Box::new(io::stderr()),
+            verbosity: Verbosity::Normal,
+            color: tty && env::var("TERM").map(|term| term != "dumb").unwrap_or(true),
+            tty: tty,
+            progress: false,
         }
     }
 
-    pub fn err(&mut self, msg: &str) {
-        // swallow any errors
-        let _ = writeln!(&mut self.err, "{}", msg);
-        ::EXIT_STATUS.store(101, Ordering::SeqCst);
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
     }
+
+    pub fn set_color(&mut self, choice: ColorChoice) {
+        self.color = match choice {
+            ColorChoice::Auto => self.color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        };
+    }
+
+    /// Print a status message, unless running quietly.
+    pub fn info(&mut self, msg: &str) {
+        if self.verbosity >= Verbosity::Normal {
+            self.clear_progress();
+            println!("{}", msg);
+        }
+    }
+
+    /// Print a message only when running verbosely.
+    pub fn verbose(&mut self, msg: &str) {
+        if self.verbosity >= Verbosity::Verbose {
+            self.clear_progress();
+            println!("{}", msg);
+        }
+    }
+
+    /// Print a note, such as a hint about what else could be done, unless
+    /// running quietly.
+    pub fn note(&mut self, msg: &str) {
+        if self.verbosity >= Verbosity::Normal {
+            self.prefixed(CYAN, "note", msg);
+        }
+    }
+
+    /// Print a warning about something that did not stop the subcommand,
+    /// unless running quietly.
+    pub fn warn(&mut self, msg: &str) {
+        if self.verbosity >= Verbosity::Normal {
+            self.prefixed(YELLOW, "warning", msg);
+        }
+    }
+
+    /// Print an error. Whether rustbook fails, and with which status, is up
+    /// to the error the subcommand returns.
+    pub fn error(&mut self, msg: &str) {
+        self.prefixed(RED, "error", msg);
+    }
+
+    /// Show that `done` of `total` things have been worked through, the last
+    /// of them being `msg`, on a line that the next progress replaces. Only
+    /// shown on terminals, and not when running quietly or verbosely, as
+    /// verbose messages already say what is going on.
+    pub fn progress(&mut self, done: usize, total: usize, msg: &str) {
+        if !self.tty || self.verbosity != Verbosity::Normal { return }
+        let _ = write!(&mut self.err, "\r\x1b[K[{}/{}] {}", done, total, msg);
+        let _ = self.err.flush();
+        self.progress = true;
+    }
+
+    /// Remove the progress line, if it is showing.
+    pub fn clear_progress(&mut self) {
+        if self.progress {
+            let _ = write!(&mut self.err, "\r\x1b[K");
+            let _ = self.err.flush();
+            self.progress = false;
+        }
+    }
+
+    fn prefixed(&mut self, color: &str, prefix: &str, msg: &str) {
+        self.clear_progress();
+        let _ = if self.color {
+            writeln!(&mut self.err, "{}{}:{} {}", color, prefix, RESET, msg)
+        } else {
+            writeln!(&mut self.err, "{}: {}", prefix, msg)
+        };
+    }
+}
+
+#[cfg(unix)]
+fn stderr_is_tty() -> bool {
+    use std::os::raw::c_int;
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+    }
+    unsafe { isatty(2) != 0 }
+}
+
+#[cfg(not(unix))]
+fn stderr_is_tty() -> bool {
+    false
 }
@@ -8,68 +8,684 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Implementation of the `test` subcommand. Just a stub for now.
+//! Implementation of the `test` subcommand. Runs the code examples of every
+//! chapter with the rustdoc built into rustbook.
 
-use subcommand::Subcommand;
-use error::{err, CliResult, CommandResult};
+use subcommand::{parse_jobs, parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
+use error::{err, ChapterError, CliResult, CommandResult, Error};
 use term::Term;
-use book;
+use book::{self, Lines, Selection};
+use build;
+use markdown;
+use pool;
 
-use std::fs::File;
 use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
+use rustc_back::tempdir::TempDir;
+
+/// The environment variable that has rustbook run as rustdoc, with its
+/// arguments, instead. rustdoc's test harness exits the process when a test
+/// fails, so every chapter is tested in a rustbook process of its own; this
+/// is for those alone, and so is not a subcommand.
+pub const RUSTDOC_ENV: &'static str = "RUSTBOOK_INTERNAL_RUSTDOC";
+
+struct Test {
+    book: BookArgs,
+    harness: Harness,
+    /// The arguments before `--`: the book's directory, perhaps, and
+    /// filters, told apart by `sort_args`.
+    args: Vec<String>,
+    filters: Vec<String>,
+    skip: Vec<String>,
+    jobs: usize,
+    junit: Option<PathBuf>,
+    json: Option<PathBuf>,
+}
 
-struct Test;
+const TEST_OPTS: &'static [Opt] = &[
+    Opt { short: "L", long: "library-path", value: "DIR",
+          help: "Add DIR to the library search path of the examples" },
+    Opt { short: "", long: "extern", value: "NAME=PATH",
+          help: "Make the crate at PATH available to the examples as NAME" },
+    Opt { short: "", long: "crate-name", value: "NAME",
+          help: "Link every example against the crate NAME, like a crate's own docs" },
+    Opt { short: "", long: "sysroot", value: "DIR",
+          help: "Look for the standard library in DIR (default: the Rust \
+                 installation rustbook is part of)" },
+    Opt { short: "", long: "skip", value: "FILTER",
+          help: "Leave out the chapters or code blocks matching FILTER" },
+    Opt { short: "j", long: "jobs", value: "N", help: "Test N chapters at a time (default: 1)" },
+    Opt { short: "", long: "junit", value: "FILE", help: "Write a JUnit XML report to FILE" },
+    Opt { short: "", long: "json", value: "FILE",
+          help: "Write a report to FILE, one JSON object per example" },
+];
 
 pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
     if name == "test" {
-        Some(Box::new(Test))
+        Some(Box::new(Test::new()))
     } else {
         None
     }
 }
 
+impl Test {
+    fn new() -> Test {
+        Test {
+            book: BookArgs::new(),
+            harness: Harness {
+                library_paths: vec![],
+                externs: vec![],
+                crate_name: None,
+                sysroot: None,
+                src: PathBuf::new(),
+                tmp: PathBuf::new(),
+            },
+            args: vec![],
+            filters: vec![],
+            skip: vec![],
+            jobs: 1,
+            junit: None,
+            json: None,
+        }
+    }
+
+    /// Tell the book's directory from the filters among the arguments
+    /// before `--`: unless `--src` is given, it is the first of them if that
+    /// is a directory. The rest are filters, as is everything after `--`.
+    fn sort_args(&mut self) {
+        let mut args = mem::replace(&mut self.args, vec![]);
+        if self.book.src.is_none() && args.first().map_or(false, |arg| Path::new(arg).is_dir()) {
+            self.book.src = Some(PathBuf::from(args.remove(0)));
+        }
+        args.extend(self.filters.drain(..));
+        self.filters = args;
+    }
+}
+
+/// How to run rustdoc on the chapters of a book. Shared by the threads
+/// testing them.
+#[derive(Clone)]
+struct Harness {
+    library_paths: Vec<String>,
+    externs: Vec<String>,
+    crate_name: Option<String>,
+    sysroot: Option<String>,
+    /// The directory containing the chapters.
+    src: PathBuf,
+    /// Where chapters are copied to when they need changing.
+    tmp: PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// The result of a single code example.
+struct Example {
+    /// The line its code block starts on.
+    line: usize,
+    status: Status,
+    /// What the test harness printed about its failure.
+    message: String,
+}
+
+/// What testing a single chapter came to.
+struct Outcome {
+    success: bool,
+    examples: Vec<Example>,
+    /// Everything rustdoc printed, for failures that are not down to any
+    /// single example.
+    output: String,
+    duration: Duration,
+}
+
+impl Outcome {
+    fn count(&self, status: Status) -> usize {
+        self.examples.iter().filter(|e| e.status == status).count()
+    }
+
+    /// Whether rustdoc failed without any example failing, e.g. because the
+    /// chapter could not be read.
+    fn is_error(&self) -> bool {
+        !self.success && self.count(Status::Failed) == 0
+    }
+
+    /// Why the chapter failed, in a line.
+    fn failure(&self) -> String {
+        let lines = self.examples.iter()
+                                 .filter(|e| e.status == Status::Failed)
+                                 .map(|e| e.line.to_string())
+                                 .collect::<Vec<_>>();
+        if !lines.is_empty() {
+            return format!("the examples on lines {} failed", lines.join(", "));
+        }
+        match self.output.lines().map(str::trim).find(|line| !line.is_empty()) {
+            Some(line) => format!("`rustdoc` failed: {}", line),
+            None => "`rustdoc` failed".to_string(),
+        }
+    }
+}
+
+/// A chapter being tested.
+struct Chapter {
+    section: String,
+    title: String,
+    path: PathBuf,
+}
+
+impl Harness {
+    fn test_chapter(&self, path: &Path, lines: &Lines) -> Outcome {
+        let start = Instant::now();
+        let mut outcome = match self.run_rustdoc(path, lines) {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome {
+                success: false,
+                examples: vec![],
+                output: format!("error: {}", e),
+                duration: Duration::from_secs(0),
+            },
+        };
+        outcome.duration = start.elapsed();
+        outcome
+    }
+
+    fn run_rustdoc(&self, path: &Path, lines: &Lines) -> CommandResult<Outcome> {
+        let src = self.src.join(path);
+        let input = if self.crate_name.is_some() || !lines.is_everything() {
+            let mut contents = String::new();
+            File::open(&src).and_then(|mut f| f.read_to_string(&mut contents))
+                            .map_err(|e| Error::io(&src, e))?;
+            let contents = prepare_chapter(&contents, self.crate_name.as_ref().map(|s| &s[..]),
+                                           lines);
+            let copy = self.tmp.join(path);
+            build::write_file(&copy, contents.as_bytes())?;
+            copy
+        } else {
+            src.clone()
+        };
+
+        let mut cmd = Command::new(env::current_exe()?);
+        cmd.env(RUSTDOC_ENV, "1").arg(&input).arg("--test");
+        for path in &self.library_paths {
+            cmd.arg("-L").arg(path);
+        }
+        for extern_crate in &self.externs {
+            cmd.arg("--extern").arg(extern_crate);
+        }
+        if let Some(ref sysroot) = self.sysroot {
+            cmd.arg("--sysroot").arg(sysroot);
+        }
+        let output = match cmd.output() {
+            Ok(output) => output,
+            Err(e) => return Err(err(&format!("could not execute rustdoc: {}", e))),
+        };
+
+        // test names mention the file tested, which should be the chapter
+        // rather than its copy
+        let output_text = format!("{}{}",
+                                  String::from_utf8_lossy(&output.stdout),
+                                  String::from_utf8_lossy(&output.stderr))
+            .replace(&input.display().to_string(), &src.display().to_string());
+        Ok(Outcome {
+            success: output.status.success(),
+            examples: parse_examples(&output_text),
+            output: output_text,
+            duration: Duration::from_secs(0),
+        })
+    }
+}
+
 impl Subcommand for Test {
-    fn parse_args(&mut self, _: &[String]) -> CliResult<()> {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, TEST_OPTS])?;
+        // whether the first argument is the book's directory or a filter is
+        // only looked into when the tests are run
+        let dashes = matches.dashes.unwrap_or(matches.free.len());
+        self.filters = matches.free.split_off(dashes);
+        self.args = mem::replace(&mut matches.free, vec![]);
+        self.book.parse(&mut matches)?;
+        self.skip = matches.opt_strs("skip");
+        self.harness.library_paths = matches.opt_strs("library-path");
+        self.harness.externs = matches.opt_strs("extern");
+        if let Some(bad) = self.harness.externs.iter().find(|e| !e.contains('=')) {
+            return Err(err(&format!("invalid --extern `{}`, expected NAME=PATH", bad)));
+        }
+        self.harness.crate_name = matches.opt_str("crate-name");
+        self.harness.sysroot = matches.opt_str("sysroot");
+        if let Some(jobs) = matches.opt_str("jobs") {
+            self.jobs = parse_jobs(&jobs)?;
+        }
+        self.junit = matches.opt_str("junit").map(PathBuf::from);
+        self.json = matches.opt_str("json").map(PathBuf::from);
         Ok(())
     }
-    fn usage(&self) {}
+    fn usage(&self) {
+        print_usage("rustbook test [<src>] [options] [<filter>...] [-- <filter>...]",
+                    "Run the code examples of the book in <src> (default: the current \
+                     directory) as tests.\n\
+                     A filter picks out chapters by path (`intro.md`), section number \
+                     (`3.2`, including its\nsubsections) or part of their title, or a \
+                     single code block by path and line (`intro.md:12`).\n\
+                     The first argument is <src> if it is a directory and `--src` is not \
+                     given; filters after `--`\nnever are.",
+                    &[BOOK_OPTS, TEST_OPTS]);
+    }
     fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
-        let cwd = env::current_dir().unwrap();
-        let src = cwd.clone();
-
-        let mut summary = File::open(&src.join("SUMMARY.md"))?;
-        match book::parse_summary(&mut summary, &src) {
-            Ok(book) => {
-                for (_, item) in book.iter() {
-                    let output_result = Command::new("rustdoc")
-                        .arg(&item.path)
-                        .arg("--test")
-                        .output();
-                    match output_result {
-                        Ok(output) => {
-                            if !output.status.success() {
-                                term.err(&format!("{}\n{}",
-                                         String::from_utf8_lossy(&output.stdout),
-                                         String::from_utf8_lossy(&output.stderr)));
-                                return Err(err("some tests failed"));
-                            }
-
-                        }
-                        Err(e) => {
-                            let message = format!("could not execute `rustdoc`: {}", e);
-                            return Err(err(&message))
-                        }
-                    }
+        self.sort_args();
+        self.book.configure(term);
+        let config = self.book.load_config()?;
+
+        let book = book::load(&config, term)?;
+        let selection = Selection::new(&self.filters, &self.skip);
+        let mut chapters = vec![];
+        let mut jobs = vec![];
+        for (section, item) in book.iter() {
+            if item.is_draft() || !selection.includes(&section, item) { continue }
+            jobs.push((item.path.clone(), selection.lines(&section, item)));
+            chapters.push(Chapter {
+                section: section,
+                title: markdown::inline_to_text(&item.title),
+                path: item.path.clone(),
+            });
+        }
+        if chapters.is_empty() && !selection.is_everything() {
+            return Err(Error::Usage("no chapters match the filters".to_string()));
+        }
+
+        let tmp = TempDir::new("rustbook")?;
+        let mut harness = self.harness.clone();
+        harness.src = config.src.clone();
+        harness.tmp = tmp.path().to_path_buf();
+
+        let mut reported = 0;
+        let outcomes = pool::map(jobs, self.jobs, move |(path, lines)| {
+            harness.test_chapter(&path, &lines)
+        }, |outcome| {
+            report(term, &chapters[reported], outcome);
+            reported += 1;
+            term.progress(reported, chapters.len(),
+                          &format!("Tested {}", chapters[reported - 1].path.display()));
+        });
+        term.clear_progress();
+        let outcomes = match outcomes {
+            Some(outcomes) => outcomes,
+            None => return Err(err("a thread testing chapters died")),
+        };
+
+        let results: Vec<(&Chapter, &Outcome)> = chapters.iter().zip(&outcomes).collect();
+        let title = config.title.clone().unwrap_or_else(|| "book".to_string());
+        if let Some(ref path) = self.junit {
+            File::create(path).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                write_junit(&mut out, &title, &results)?;
+                out.flush()
+            }).map_err(|e| Error::io(path, e))?;
+        }
+        if let Some(ref path) = self.json {
+            File::create(path).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                write_json(&mut out, &results)?;
+                out.flush()
+            }).map_err(|e| Error::io(path, e))?;
+        }
+
+        let failed = results.iter().filter(|&&(_, o)| !o.success).map(|&(chapter, outcome)| {
+            ChapterError { path: chapter.path.clone(), message: outcome.failure() }
+        }).collect::<Vec<_>>();
+        let chapters_failed = failed.len();
+        let count = |status| outcomes.iter().map(|o| o.count(status)).sum::<usize>();
+        term.info(&format!("{} of {} chapters passed; {} examples passed, {} failed, \
+                            {} ignored",
+                           chapters.len() - chapters_failed, chapters.len(),
+                           count(Status::Passed), count(Status::Failed),
+                           count(Status::Ignored)));
+        if chapters_failed > 0 {
+            return Err(Error::Test { failed: failed, total: chapters.len() });
+        }
+        Ok(())
+    }
+}
+
+/// Print the failures of a chapter, or just how it went when verbose.
+fn report(term: &mut Term, chapter: &Chapter, outcome: &Outcome) {
+    term.verbose(&format!("{}: {} examples passed, {} failed, {} ignored",
+                          chapter.path.display(),
+                          outcome.count(Status::Passed),
+                          outcome.count(Status::Failed),
+                          outcome.count(Status::Ignored)));
+    for example in outcome.examples.iter().filter(|e| e.status == Status::Failed) {
+        term.error(&format!("{}:{}: example failed\n{}",
+                            chapter.path.display(), example.line, example.message));
+    }
+    if outcome.is_error() {
+        term.error(&format!("{} failed:\n{}", chapter.path.display(), outcome.output));
+    }
+}
+
+/// Pick the results of the examples out of the test harness's output, where
+/// they look like `test src/foo.md - (line 3) ... ok`.
+fn parse_examples(output: &str) -> Vec<Example> {
+    let mut examples = vec![];
+    for line in output.lines() {
+        if !line.starts_with("test ") { continue }
+        let (name, status) = match line.rfind(" ... ") {
+            Some(i) => (&line[5..i], &line[i + 5..]),
+            None => continue,
+        };
+        let status = match status.trim() {
+            "ok" => Status::Passed,
+            "FAILED" => Status::Failed,
+            "ignored" => Status::Ignored,
+            _ => continue,
+        };
+        let line = match name.rfind("(line ") {
+            Some(i) => name[i + 6..].trim_right_matches(')').parse().ok(),
+            None => None,
+        };
+        if let Some(line) = line {
+            examples.push(Example {
+                line: line,
+                status: status,
+                message: failure_message(output, name).unwrap_or_default(),
+            });
+        }
+    }
+    examples.sort_by_key(|e| e.line);
+    examples
+}
+
+/// The output of the failed test `name`, which the harness prints after a
+/// `---- <name> stdout ----` line.
+fn failure_message(output: &str, name: &str) -> Option<String> {
+    let header = format!("---- {} stdout ----\n", name);
+    let start = match output.find(&header) {
+        Some(i) => i + header.len(),
+        None => return None,
+    };
+    let rest = &output[start..];
+    let end = ["\n---- ", "\nfailures:\n"].iter()
+                                           .filter_map(|end| rest.find(end))
+                                           .min()
+                                           .unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string())
+}
+
+/// Write a JUnit report with a test suite per chapter and a test case per
+/// example. Chapters without examples, and chapters rustdoc failed on
+/// before getting to them, get a test case of their own.
+fn write_junit(out: &mut Write,
+               title: &str,
+               results: &[(&Chapter, &Outcome)]) -> io::Result<()> {
+    let chapter_case = |o: &Outcome| (o.examples.is_empty() || o.is_error()) as usize;
+    let tests = results.iter().map(|&(_, o)| o.examples.len() + chapter_case(o));
+    let failures = results.iter().map(|&(_, o)| o.count(Status::Failed) + o.is_error() as usize);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<testsuites name="{}" tests="{}" failures="{}">"#,
+             escape_xml(title), tests.sum::<usize>(), failures.sum::<usize>())?;
+
+    for &(chapter, outcome) in results {
+        let path = escape_xml(&chapter.path.display().to_string());
+        let title = escape_xml(&chapter.title);
+        writeln!(out, r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{}">"#,
+                 path,
+                 outcome.examples.len() + chapter_case(outcome),
+                 outcome.count(Status::Failed) + outcome.is_error() as usize,
+                 outcome.count(Status::Ignored),
+                 seconds(outcome.duration))?;
+        for example in &outcome.examples {
+            write!(out, r#"    <testcase classname="{}" name="{} (line {})""#,
+                   path, title, example.line)?;
+            match example.status {
+                Status::Passed => writeln!(out, "/>")?,
+                Status::Ignored => writeln!(out, "><skipped/></testcase>")?,
+                Status::Failed => {
+                    writeln!(out, r#"><failure message="example failed">{}</failure></testcase>"#,
+                             escape_xml(&example.message))?
                 }
             }
-            Err(errors) => {
-                for err in errors {
-                    term.err(&err[..]);
+        }
+        if chapter_case(outcome) == 1 {
+            write!(out, r#"    <testcase classname="{}" name="{}""#, path, title)?;
+            if outcome.is_error() {
+                writeln!(out, r#"><failure message="rustdoc failed">{}</failure></testcase>"#,
+                         escape_xml(&outcome.output))?;
+            } else {
+                writeln!(out, "/>")?;
+            }
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")?;
+    out.flush()
+}
+
+/// Write a JSON object per example, and one for every chapter that failed
+/// other than through its examples.
+fn write_json(out: &mut Write, results: &[(&Chapter, &Outcome)]) -> io::Result<()> {
+    for &(chapter, outcome) in results {
+        let prefix = format!(r#"{{"chapter":{},"section":{},"title":{}"#,
+                             markdown::json_string(&chapter.path.display().to_string()),
+                             markdown::json_string(&chapter.section),
+                             markdown::json_string(&chapter.title));
+        for example in &outcome.examples {
+            let status = match example.status {
+                Status::Passed => "passed",
+                Status::Failed => "failed",
+                Status::Ignored => "ignored",
+            };
+            writeln!(out, r#"{},"line":{},"status":"{}","message":{}}}"#,
+                     prefix, example.line, status, markdown::json_string(&example.message))?;
+        }
+        if outcome.is_error() {
+            writeln!(out, r#"{},"line":null,"status":"error","message":{}}}"#,
+                     prefix, markdown::json_string(&outcome.output))?;
+        }
+    }
+    out.flush()
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{}.{:03}", duration.as_secs(), duration.subsec_nanos() / 1_000_000)
+}
+
+/// Escape text for XML, leaving out the control characters it cannot hold.
+fn escape_xml(s: &str) -> String {
+    let s: String = s.chars()
+                     .filter(|&c| c >= ' ' || c == '\t' || c == '\n' || c == '\r')
+                     .collect();
+    markdown::escape_html(&s)
+}
+
+/// Whether rustdoc takes a code block with the info string `info`, such as
+/// `rust,ignore`, for Rust: if it has no tags, or one of rustdoc's own.
+fn is_rust(info: &str) -> bool {
+    let mut seen_rust = false;
+    let mut seen_other = false;
+    for tag in info.split(|c| c == ',' || c == ' ' || c == '\t').filter(|t| !t.is_empty()) {
+        let error_code = tag.len() == 5 && tag.starts_with('E') &&
+                         tag[1..].chars().all(|c| c.is_digit(10));
+        match tag {
+            "rust" | "ignore" | "should_panic" | "no_run" | "test_harness" |
+            "compile_fail" => seen_rust = true,
+            _ if error_code => seen_rust = true,
+            _ => seen_other = true,
+        }
+    }
+    seen_rust || !seen_other
+}
+
+/// The byte length of the indentation that makes `line` part of an
+/// indented code block: four columns of spaces or tabs.
+fn code_indent(line: &str) -> usize {
+    let mut column = 0;
+    for (i, c) in line.char_indices() {
+        if column >= 4 { return i }
+        match c {
+            ' ' => column += 1,
+            '\t' => column += 4 - column % 4,
+            _ => return i,
+        }
+    }
+    line.len()
+}
+
+/// Prepare a copy of `chapter` for testing. Code blocks other than the
+/// selected `lines` are left out: fenced ones are turned into plain text,
+/// and indented ones into blank lines. If there is a `crate_name`,
+/// `extern crate <name>;` is added to every selected Rust block that does
+/// not mention the crate already, as rustdoc does for the examples in a
+/// crate's own documentation. It goes at the start of the block's first line
+/// of code, so that the line numbers in test names stay the same.
+fn prepare_chapter(chapter: &str, crate_name: Option<&str>, selected: &Lines) -> String {
+    let extern_crate = crate_name.map(|name| format!("extern crate {};", name));
+    let mut lines: Vec<String> = chapter.split('\n').map(|l| l.to_string()).collect();
+
+    // rustdoc numbers a code block by the line it starts on, counting the
+    // fence of a fenced one, plus one for every fenced code block before it;
+    // test names and filters follow suit
+    let mut fences = 0;
+    for block in markdown::code_blocks(chapter) {
+        let code = block.code;
+        let fenced = block.fence.is_some();
+        let (number, rust) = match block.fence {
+            Some(ref fence) => (code.start + fences, is_rust(fence.info)),
+            None => (code.start + 1 + fences, true),
+        };
+        if !selected.includes(number) {
+            match block.fence {
+                Some(ref fence) => {
+                    let text = iter::repeat(fence.fence).take(fence.len).collect::<String>();
+                    lines[code.start - 1] = format!("{}text", text);
+                }
+                None => {
+                    for line in &mut lines[code.clone()] {
+                        line.clear();
+                    }
                 }
-                return Err(err("there was an error"))
             }
         }
-        Ok(()) // lol
+        if fenced {
+            fences += 1;
+        }
+        if !selected.includes(number) || !rust { continue }
+
+        let extern_crate = match extern_crate {
+            Some(ref extern_crate) => extern_crate,
+            None => continue,
+        };
+        if lines[code.clone()].iter().any(|l| l.contains(extern_crate)) { continue }
+        // the code of an indented block starts after its indentation
+        let indent = |line: &str| if fenced { 0 } else { code_indent(line) };
+        // crate attributes have to come first
+        let first = lines[code.clone()].iter().position(|l| {
+            let l = &l[indent(l)..];
+            let l = if l.starts_with("# ") { &l[2..] } else { l }.trim();
+            !l.is_empty() && l != "#" && !l.starts_with("#![")
+        });
+        if let Some(first) = first {
+            let line = &mut lines[code.start + first];
+            let prepared = {
+                let (indentation, rest) = line.split_at(indent(line));
+                if rest.starts_with("# ") {
+                    format!("{}# {} {}", indentation, extern_crate, &rest[2..])
+                } else {
+                    format!("{}{} {}", indentation, extern_crate, rest)
+                }
+            };
+            *line = prepared;
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use book::Lines;
+    use error::CliResult;
+    use subcommand::Subcommand;
+    use super::{is_rust, prepare_chapter, Test};
+
+    const CHAPTER: &'static str = "% A\n\n    let a = 1;\n\n```\nlet b = 2;\n```\n\n\
+                                   ```toml\nc = 3\n```\n\n```rust,ignore\n# #![allow(x)]\n\
+                                   let d = 4;\n```\n\n    # fn main() {\n    let e = 5;\n";
+
+    fn everything() -> Lines {
+        Lines { only: None, skip: vec![] }
+    }
+
+    #[test]
+    fn rust_blocks() {
+        assert!(is_rust(""));
+        assert!(is_rust("rust"));
+        assert!(is_rust("rust,ignore"));
+        assert!(is_rust("should_panic E0277"));
+        assert!(is_rust("text rust"));
+        assert!(!is_rust("text"));
+        assert!(!is_rust("toml"));
+        assert!(!is_rust("ignore-me"));
+    }
+
+    #[test]
+    fn adds_extern_crate_to_rust_blocks() {
+        assert_eq!(prepare_chapter(CHAPTER, Some("k"), &everything()),
+                   "% A\n\n    extern crate k; let a = 1;\n\n```\nextern crate k; let b = 2;\n\
+                    ```\n\n```toml\nc = 3\n```\n\n```rust,ignore\n# #![allow(x)]\n\
+                    extern crate k; let d = 4;\n```\n\n    # extern crate k; fn main() {\n    \
+                    let e = 5;\n");
+    }
+
+    #[test]
+    fn leaves_out_unselected_blocks() {
+        // blocks are numbered by their first line, plus one for every fenced
+        // block before them
+        let lines = Lines { only: Some(vec![5, 18]), skip: vec![18] };
+        assert_eq!(prepare_chapter(CHAPTER, None, &lines),
+                   "% A\n\n\n\n```\nlet b = 2;\n```\n\n```text\nc = 3\n```\n\n\
+                    ```text\n# #![allow(x)]\nlet d = 4;\n```\n\n\n\n");
+        let lines = Lines { only: Some(vec![3, 21]), skip: vec![] };
+        assert_eq!(prepare_chapter(CHAPTER, Some("k"), &lines),
+                   "% A\n\n    extern crate k; let a = 1;\n\n```text\nlet b = 2;\n```\n\n\
+                    ```text\nc = 3\n```\n\n```text\n# #![allow(x)]\nlet d = 4;\n```\n\n    \
+                    # extern crate k; fn main() {\n    let e = 5;\n");
+    }
+
+    #[test]
+    fn book_directory_and_filters() {
+        // tests run in the crate's directory, where `src` is a directory
+        fn parse(args: &[&str]) -> CliResult<(Option<PathBuf>, Vec<String>)> {
+            let mut test = Test::new();
+            let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            test.parse_args(&args)?;
+            test.sort_args();
+            Ok((test.book.src, test.filters))
+        }
+        fn strings(strs: &[&str]) -> Vec<String> {
+            strs.iter().map(|s| s.to_string()).collect()
+        }
+        let src = Some(PathBuf::from("src"));
+
+        assert_eq!(parse(&["3.2"]).ok().unwrap(), (None, strings(&["3.2"])));
+        assert_eq!(parse(&["--", "3.2"]).ok().unwrap(), (None, strings(&["3.2"])));
+        assert_eq!(parse(&["intro.md", "3.2"]).ok().unwrap(),
+                   (None, strings(&["intro.md", "3.2"])));
+        assert_eq!(parse(&["src", "3.2", "--", "4"]).ok().unwrap(),
+                   (src.clone(), strings(&["3.2", "4"])));
+        // a directory is a filter after `--`, or once the book's is known
+        assert_eq!(parse(&["--skip", "x", "--", "src", "3.2"]).ok().unwrap(),
+                   (None, strings(&["src", "3.2"])));
+        assert_eq!(parse(&["src", "src"]).ok().unwrap(), (src.clone(), strings(&["src"])));
+        assert_eq!(parse(&["--src", "src", "src"]).ok().unwrap(), (src, strings(&["src"])));
     }
 }
@@ -0,0 +1,235 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A book's theme: the stylesheet, script and templates its pages are made
+//! of. The files of the theme directory override the built-in ones of the
+//! same name, and any other files in it are copied into the built book. Also
+//! the implementation of the `theme` subcommand, which shows the theme or
+//! writes the built-in one to the theme directory to start from.
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use subcommand::{parse_opts, print_usage, BookArgs, Opt, Subcommand, BOOK_OPTS};
+use error::{err, CliResult, CommandResult, Error};
+use config::Config;
+use template::{Template, Value};
+use term::Term;
+
+/// A file every theme has, whether the book's own or built in.
+pub struct ThemeFile {
+    pub name: &'static str,
+    pub default: &'static [u8],
+    pub help: &'static str,
+}
+
+pub const FILES: &'static [ThemeFile] = &[
+    ThemeFile { name: "rustbook.css", default: include_bytes!("static/rustbook.css"),
+                help: "The stylesheet of every page" },
+    ThemeFile { name: "rustbook.js", default: include_bytes!("static/rustbook.js"),
+                help: "The script of every page" },
+    ThemeFile { name: "head.html", default: include_bytes!("static/head.html"),
+                help: "Added to the <head> of every page" },
+    ThemeFile { name: "page.html", default: include_bytes!("static/page.html"),
+                help: "The layout of every page, around the chapter" },
+    ThemeFile { name: "toc.html", default: include_bytes!("static/toc.html"),
+                help: "The table of contents, at the side of every page" },
+    ThemeFile { name: "header.html", default: include_bytes!("static/header.html"),
+                help: "Above the chapter, on every page" },
+    ThemeFile { name: "footer.html", default: include_bytes!("static/footer.html"),
+                help: "Below the chapter, on every page" },
+];
+
+pub struct Theme {
+    pub css: Vec<u8>,
+    pub js: Vec<u8>,
+    /// The templates, by the names of their files.
+    templates: Vec<(&'static str, Template)>,
+    /// The other files of the theme directory, relative to it.
+    pub extra: Vec<PathBuf>,
+    dir: PathBuf,
+}
+
+impl Theme {
+    /// Load the theme of the book described by `config`, falling back to the
+    /// built-in files for those its theme directory does not have.
+    pub fn load(config: &Config) -> CliResult<Theme> {
+        let dir = config.theme_dir.clone();
+        let read = |name: &str| -> CliResult<Vec<u8>> {
+            let path = dir.join(name);
+            if !path.is_file() {
+                let file = FILES.iter().find(|file| file.name == name).unwrap();
+                return Ok(file.default.to_vec());
+            }
+            let mut data = vec![];
+            match File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+                Ok(..) => Ok(data),
+                Err(e) => Err(Error::io(&path, e)),
+            }
+        };
+        let mut templates = vec![];
+        for file in FILES.iter().filter(|file| file.name.ends_with(".html")) {
+            let path = dir.join(file.name);
+            let source = String::from_utf8(read(file.name)?).map_err(|_| {
+                Error::Config(format!("{} is not valid UTF-8", path.display()))
+            })?;
+            let template = Template::parse(&path.display().to_string(), &source)
+                                    .map_err(Error::Config)?;
+            templates.push((file.name, template));
+        }
+
+        let mut extra = vec![];
+        for path in files(&dir)? {
+            let path = path.strip_prefix(&dir).unwrap().to_path_buf();
+            if !FILES.iter().any(|file| Path::new(file.name) == path) {
+                extra.push(path);
+            }
+        }
+        Ok(Theme {
+            css: read("rustbook.css")?,
+            js: read("rustbook.js")?,
+            templates: templates,
+            extra: extra,
+            dir: dir.clone(),
+        })
+    }
+
+    /// Fill in the template `name`, such as `"page.html"`, with the values
+    /// of `context`.
+    pub fn render(&self, name: &str, context: &Value) -> CliResult<String> {
+        let template = self.template(name).expect("no such template");
+        template.render(context, &|name| self.template(name)).map_err(Error::Config)
+    }
+
+    fn template(&self, name: &str) -> Option<&Template> {
+        self.templates.iter().find(|&&(file, _)| file == name).map(|&(_, ref t)| t)
+    }
+
+    /// Write the stylesheet and the script into the build directory `tgt`,
+    /// along with the other files of the theme directory.
+    pub fn copy_files(&self, tgt: &Path) -> io::Result<()> {
+        File::create(tgt.join("rustbook.css"))?.write_all(&self.css)?;
+        File::create(tgt.join("rustbook.js"))?.write_all(&self.js)?;
+        for path in &self.extra {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(tgt.join(parent))?;
+            }
+            fs::copy(self.dir.join(path), tgt.join(path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Every file in the theme directory `dir` and below it, leaving out hidden
+/// ones. A missing directory has none.
+pub fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(dir)? {
+            entries.push(entry?.path());
+        }
+        entries.sort();
+        for path in entries {
+            let hidden = path.file_name().map_or(false, |name| {
+                name.to_string_lossy().starts_with('.')
+            });
+            if hidden {
+                continue;
+            } else if path.is_dir() {
+                walk(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = vec![];
+    if dir.is_dir() {
+        walk(dir, &mut files)?;
+    }
+    Ok(files)
+}
+
+struct ThemeCommand {
+    book: BookArgs,
+    eject: bool,
+    force: bool,
+}
+
+const THEME_OPTS: &'static [Opt] = &[
+    Opt { short: "", long: "eject", value: "",
+          help: "Write the built-in theme to the theme directory, to start from" },
+    Opt { short: "f", long: "force", value: "",
+          help: "With --eject, overwrite the files the theme directory already has" },
+];
+
+pub fn parse_cmd(name: &str) -> Option<Box<Subcommand>> {
+    if name == "theme" {
+        Some(Box::new(ThemeCommand { book: BookArgs::new(), eject: false, force: false }))
+    } else {
+        None
+    }
+}
+
+impl Subcommand for ThemeCommand {
+    fn parse_args(&mut self, args: &[String]) -> CliResult<()> {
+        let mut matches = parse_opts(args, &[BOOK_OPTS, THEME_OPTS])?;
+        self.book.parse(&mut matches)?;
+        if let Some(arg) = matches.free.first() {
+            return Err(err(&format!("unexpected argument `{}`", arg)));
+        }
+        self.eject = matches.opt_present("eject");
+        self.force = matches.opt_present("force");
+        if self.force && !self.eject {
+            return Err(err("`--force` can only be used with `--eject`"));
+        }
+        Ok(())
+    }
+    fn usage(&self) {
+        print_usage("rustbook theme [<src>] [options]",
+                    "Show which files of the theme of the book in <src> (default: the current\n\
+                     directory) are its own, or write the built-in theme to its theme directory.",
+                    &[BOOK_OPTS, THEME_OPTS]);
+    }
+    fn execute(&mut self, term: &mut Term) -> CommandResult<()> {
+        self.book.configure(term);
+        let config = self.book.load_config()?;
+        let dir = &config.theme_dir;
+
+        if self.eject {
+            fs::create_dir_all(dir)?;
+            for file in FILES {
+                let path = dir.join(file.name);
+                if path.exists() && !self.force {
+                    term.info(&format!("Kept {}, which already exists", path.display()));
+                    continue;
+                }
+                File::create(&path)?.write_all(file.default)?;
+                term.info(&format!("Created {}", path.display()));
+            }
+            return Ok(());
+        }
+
+        let theme = Theme::load(&config)?;
+        term.info(&format!("Theme directory: {}", dir.display()));
+        let width = FILES.iter().map(|file| file.name.len()).max().unwrap();
+        for file in FILES {
+            let origin = if dir.join(file.name).is_file() { "own" } else { "built-in" };
+            term.info(&format!("  {:3$}  {:8}  {}", file.name, origin, file.help, width));
+        }
+        for path in &theme.extra {
+            term.info(&format!("  {}  (copied into the book)", path.display()));
+        }
+        Ok(())
+    }
+}